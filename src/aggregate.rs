@@ -0,0 +1,605 @@
+extern crate chrono;
+extern crate chrono_tz;
+
+use self::chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Timelike,
+};
+use self::chrono_tz::Tz;
+use std::collections::BTreeMap;
+
+use date_time_tz::DateTimeTz;
+use types::{Error, Recordable};
+
+/// How to divide time into the buckets that records are grouped into for aggregation. Buckets
+/// follow the clock and calendar of a `BucketZone`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bucket {
+    /// Buckets of a fixed length, counted from midnight on 1970-01-01 on the local clock. The
+    /// length must be positive, or aggregating fails with `Error::InvalidBucket`.
+    Fixed(Duration),
+    Minute,
+    Hour,
+    Day,
+
+    /// Weeks that start on Monday, as in ISO 8601.
+    Week,
+    Month,
+    Year,
+}
+
+/// The time zone whose clock and calendar decide which bucket a record falls into.
+///
+/// Calendar buckets follow the local calendar, so a day is whatever lies between two local
+/// midnights, and may be 23 or 25 hours long across a daylight saving transition. If midnight does
+/// not exist on some day, that day starts at the first moment after the gap. Buckets shorter than
+/// a day are measured on the local clock but never merge the two passes through a repeated hour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BucketZone {
+    Utc,
+
+    /// The zone recorded in each record's own timestamp. A run at 23:30 in US/Central falls into
+    /// that local day in US/Central, whatever the UTC date. Records from different zones fall into
+    /// different buckets unless the buckets happen to start at the same moment.
+    Record,
+
+    /// A single zone chosen by the caller.
+    Named(Tz),
+}
+
+/// How to combine the values extracted from the records in a bucket into a single value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reducer {
+    /// The number of records in the bucket. The extracted values are ignored.
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean,
+
+    /// The value of the earliest record in the bucket.
+    First,
+
+    /// The value of the latest record in the bucket.
+    Last,
+}
+
+/// The result of aggregating the records within one bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    /// The start of the bucket, inclusive, in the zone of the bucketing.
+    pub start: DateTimeTz,
+
+    /// The end of the bucket, exclusive.
+    pub end: DateTimeTz,
+
+    /// The number of records in the bucket.
+    pub count: usize,
+
+    /// The reduced value.
+    pub value: f64,
+}
+
+/// The running state of the reducers over one bucket.
+pub(crate) struct Accumulator {
+    pub(crate) count: usize,
+    pub(crate) sum: f64,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) first: f64,
+    pub(crate) last: f64,
+}
+
+impl Accumulator {
+    fn new(value: f64) -> Accumulator {
+        Accumulator {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+            first: value,
+            last: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.last = value;
+    }
+
+    fn reduce(&self, reducer: Reducer) -> f64 {
+        match reducer {
+            Reducer::Count => self.count as f64,
+            Reducer::Sum => self.sum,
+            Reducer::Min => self.min,
+            Reducer::Max => self.max,
+            Reducer::Mean => self.sum / self.count as f64,
+            Reducer::First => self.first,
+            Reducer::Last => self.last,
+        }
+    }
+}
+
+/// Group records, which must come in time order, into buckets and reduce the extracted values in
+/// each. Only buckets containing at least one record are returned, in time order.
+pub(crate) fn aggregate<'r, R, I, F>(
+    records: I,
+    bucket: Bucket,
+    zone: BucketZone,
+    reducer: Reducer,
+    extract: F,
+) -> Result<Vec<Aggregate>, Error>
+where
+    R: Recordable + 'r,
+    I: Iterator<Item = &'r R>,
+    F: Fn(&R) -> f64,
+{
+    Ok(summarize(records, bucket, zone, extract)?
+        .into_iter()
+        .map(|(start, (end, acc))| Aggregate {
+            start,
+            end,
+            count: acc.count,
+            value: acc.reduce(reducer),
+        })
+        .collect())
+}
+
+/// Group records, which must come in time order, into buckets keyed by their start, and
+/// accumulate the extracted values in each.
+///
+/// Fails with `Error::InvalidBucket` if the bucket is `Bucket::Fixed` with a length that is not
+/// positive, even when there are no records.
+pub(crate) fn summarize<'r, R, I, F>(
+    records: I,
+    bucket: Bucket,
+    zone: BucketZone,
+    extract: F,
+) -> Result<BTreeMap<DateTimeTz, (DateTimeTz, Accumulator)>, Error>
+where
+    R: Recordable + 'r,
+    I: Iterator<Item = &'r R>,
+    F: Fn(&R) -> f64,
+{
+    if let Bucket::Fixed(length) = bucket {
+        if length <= Duration::zero() {
+            return Err(Error::InvalidBucket);
+        }
+    }
+
+    let mut buckets: BTreeMap<DateTimeTz, (DateTimeTz, Accumulator)> = BTreeMap::new();
+    for record in records {
+        let (start, end) = bucket_of(bucket, zone, &record.timestamp())?;
+        let value = extract(record);
+        buckets
+            .entry(start)
+            .and_modify(|(_, acc)| acc.add(value))
+            .or_insert_with(|| (end, Accumulator::new(value)));
+    }
+    Ok(buckets)
+}
+
+/// The start and end of the bucket that a time falls into. Fails with `Error::InvalidBucket` if
+/// the bucket is `Bucket::Fixed` with a length that is not positive, or so long that the bucket
+/// reaches beyond the range of times that can be represented.
+pub(crate) fn bucket_of(
+    bucket: Bucket,
+    zone: BucketZone,
+    time: &DateTimeTz,
+) -> Result<(DateTimeTz, DateTimeTz), Error> {
+    let local = match zone {
+        BucketZone::Utc => time.0.with_timezone(&chrono_tz::Etc::UTC),
+        BucketZone::Record => time.0,
+        BucketZone::Named(tz) => time.0.with_timezone(&tz),
+    };
+    let (start, end) = bucket_bounds(bucket, &local).ok_or(Error::InvalidBucket)?;
+    Ok((DateTimeTz(start), DateTimeTz(end)))
+}
+
+/// The start and end of the bucket containing a time, in the time's own zone.
+fn bucket_bounds(bucket: Bucket, time: &DateTime<Tz>) -> Option<(DateTime<Tz>, DateTime<Tz>)> {
+    let tz = time.timezone();
+    let local = time.naive_local();
+    let start = bucket_start(bucket, &local)?;
+    let end = bucket_end(bucket, &start)?;
+    match bucket {
+        Bucket::Fixed(_) | Bucket::Minute | Bucket::Hour => {
+            // Keep the offset of the time itself, so that an hour which repeats when the clocks
+            // go back gives two buckets rather than one two hours long.
+            let offset = local.signed_duration_since(time.naive_utc());
+            Some((
+                tz.from_utc_datetime(&start.checked_sub_signed(offset)?),
+                tz.from_utc_datetime(&end.checked_sub_signed(offset)?),
+            ))
+        }
+        Bucket::Day | Bucket::Week | Bucket::Month | Bucket::Year => {
+            Some((resolve_local(&tz, start), resolve_local(&tz, end)))
+        }
+    }
+}
+
+/// The moment at which a zone's clock reads `local`. An ambiguous time resolves to its earlier
+/// occurrence, and a time skipped by the clocks going forward resolves to the end of the gap.
+fn resolve_local(tz: &Tz, local: NaiveDateTime) -> DateTime<Tz> {
+    let mut local = local;
+    loop {
+        match tz.from_local_datetime(&local) {
+            LocalResult::Single(time) => return time,
+            LocalResult::Ambiguous(earliest, _) => return earliest,
+            LocalResult::None => local += Duration::minutes(1),
+        }
+    }
+}
+
+/// The start of the bucket containing a time, on the same clock as the time, or None if it lies
+/// outside the range of times that can be represented.
+fn bucket_start(bucket: Bucket, time: &NaiveDateTime) -> Option<NaiveDateTime> {
+    let date = time.date();
+    match bucket {
+        Bucket::Fixed(length) => {
+            let epoch = NaiveDate::from_ymd_opt(1970, 1, 1)
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .unwrap();
+            let length = nanoseconds(length);
+            if length <= 0 {
+                return None;
+            }
+            let offset = nanoseconds(time.signed_duration_since(epoch));
+            let start = offset - offset.rem_euclid(length);
+            let seconds = start.div_euclid(NANOS_PER_SECOND);
+            if seconds.abs() > MAX_DURATION_SECONDS {
+                return None;
+            }
+            epoch
+                .checked_add_signed(Duration::seconds(seconds as i64))?
+                .checked_add_signed(Duration::nanoseconds(
+                    start.rem_euclid(NANOS_PER_SECOND) as i64
+                ))
+        }
+        Bucket::Minute => date.and_hms_opt(time.hour(), time.minute(), 0),
+        Bucket::Hour => date.and_hms_opt(time.hour(), 0, 0),
+        Bucket::Day => Some(midnight(date)),
+        Bucket::Week => date
+            .checked_sub_signed(Duration::days(i64::from(
+                date.weekday().num_days_from_monday(),
+            )))
+            .map(midnight),
+        Bucket::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).map(midnight),
+        Bucket::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).map(midnight),
+    }
+}
+
+const NANOS_PER_SECOND: i128 = 1_000_000_000;
+
+/// The most seconds that `Duration::seconds` accepts without panicking. The range of dates is far
+/// smaller still.
+const MAX_DURATION_SECONDS: i128 = 9_223_372_036_854_775;
+
+/// The whole length of a duration in nanoseconds, which, unlike `Duration::num_nanoseconds`,
+/// cannot overflow.
+fn nanoseconds(duration: Duration) -> i128 {
+    let seconds = duration.num_seconds();
+    let nanos = (duration - Duration::seconds(seconds))
+        .num_nanoseconds()
+        .unwrap_or(0);
+    i128::from(seconds) * NANOS_PER_SECOND + i128::from(nanos)
+}
+
+/// The start of the bucket following the one that starts at `start`, or None if it lies outside
+/// the range of times that can be represented.
+fn bucket_end(bucket: Bucket, start: &NaiveDateTime) -> Option<NaiveDateTime> {
+    let date = start.date();
+    match bucket {
+        Bucket::Fixed(length) => start.checked_add_signed(length),
+        Bucket::Minute => start.checked_add_signed(Duration::minutes(1)),
+        Bucket::Hour => start.checked_add_signed(Duration::hours(1)),
+        Bucket::Day => start.checked_add_signed(Duration::days(1)),
+        Bucket::Week => start.checked_add_signed(Duration::weeks(1)),
+        Bucket::Month => {
+            let (year, month) = if date.month() == 12 {
+                (date.year() + 1, 1)
+            } else {
+                (date.year(), date.month() + 1)
+            };
+            NaiveDate::from_ymd_opt(year, month, 1).map(midnight)
+        }
+        Bucket::Year => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1).map(midnight),
+    }
+}
+
+fn midnight(date: NaiveDate) -> NaiveDateTime {
+    date.and_hms_opt(0, 0, 0).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Duration, TimeZone};
+    use chrono_tz::America::Sao_Paulo;
+    use chrono_tz::Etc::UTC;
+    use chrono_tz::Tz;
+    use chrono_tz::US::Central;
+
+    use super::{aggregate, Aggregate, Bucket, BucketZone, Reducer};
+    use date_time_tz::DateTimeTz;
+    use types::{Error, Recordable};
+
+    struct Reading {
+        time: DateTimeTz,
+        value: f64,
+    }
+
+    impl Recordable for Reading {
+        fn timestamp(&self) -> DateTimeTz {
+            self.time.clone()
+        }
+
+        fn tags(&self) -> Vec<String> {
+            Vec::new()
+        }
+    }
+
+    fn time(month: u32, day: u32, hour: u32, minute: u32) -> DateTimeTz {
+        DateTimeTz(
+            UTC.with_ymd_and_hms(2019, month, day, hour, minute, 0)
+                .unwrap(),
+        )
+    }
+
+    fn readings() -> Vec<Reading> {
+        vec![
+            (time(4, 30, 23, 59), 1.0),
+            (time(5, 1, 0, 0), 2.0),
+            (time(5, 1, 0, 4), 4.0),
+            (time(5, 1, 0, 5), 8.0),
+            (time(5, 5, 12, 0), 16.0),
+            (time(5, 6, 0, 0), 32.0),
+            (time(12, 31, 0, 0), 64.0),
+        ]
+        .into_iter()
+        .map(|(time, value)| Reading { time, value })
+        .collect()
+    }
+
+    fn run(bucket: Bucket, reducer: Reducer) -> Vec<(DateTimeTz, usize, f64)> {
+        aggregate(readings().iter(), bucket, BucketZone::Utc, reducer, |r| {
+            r.value
+        })
+        .unwrap()
+        .into_iter()
+        .map(|a| (a.start, a.count, a.value))
+        .collect()
+    }
+
+    #[test]
+    fn applies_each_reducer() {
+        let day = |reducer| run(Bucket::Day, reducer)[1].2;
+        assert_eq!(day(Reducer::Count), 3.0);
+        assert_eq!(day(Reducer::Sum), 14.0);
+        assert_eq!(day(Reducer::Min), 2.0);
+        assert_eq!(day(Reducer::Max), 8.0);
+        assert_eq!(day(Reducer::Mean), 14.0 / 3.0);
+        assert_eq!(day(Reducer::First), 2.0);
+        assert_eq!(day(Reducer::Last), 8.0);
+    }
+
+    #[test]
+    fn groups_by_calendar_units() {
+        assert_eq!(
+            run(Bucket::Day, Reducer::Sum),
+            vec![
+                (time(4, 30, 0, 0), 1, 1.0),
+                (time(5, 1, 0, 0), 3, 14.0),
+                (time(5, 5, 0, 0), 1, 16.0),
+                (time(5, 6, 0, 0), 1, 32.0),
+                (time(12, 31, 0, 0), 1, 64.0),
+            ]
+        );
+        // 2019-04-29 and 2019-05-06 are Mondays.
+        assert_eq!(
+            run(Bucket::Week, Reducer::Sum),
+            vec![
+                (time(4, 29, 0, 0), 5, 31.0),
+                (time(5, 6, 0, 0), 1, 32.0),
+                (time(12, 30, 0, 0), 1, 64.0),
+            ]
+        );
+        assert_eq!(
+            run(Bucket::Month, Reducer::Sum),
+            vec![
+                (time(4, 1, 0, 0), 1, 1.0),
+                (time(5, 1, 0, 0), 5, 62.0),
+                (time(12, 1, 0, 0), 1, 64.0),
+            ]
+        );
+        assert_eq!(run(Bucket::Year, Reducer::Count).len(), 1);
+        assert_eq!(run(Bucket::Minute, Reducer::Count).len(), 7);
+        assert_eq!(run(Bucket::Hour, Reducer::Count).len(), 5);
+    }
+
+    #[test]
+    fn groups_by_fixed_lengths() {
+        assert_eq!(
+            run(Bucket::Fixed(Duration::minutes(5)), Reducer::Sum)[..3],
+            [
+                (time(4, 30, 23, 55), 1, 1.0),
+                (time(5, 1, 0, 0), 2, 6.0),
+                (time(5, 1, 0, 5), 1, 8.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_by_lengths_under_a_millisecond() {
+        let aggregates = aggregate(
+            readings().iter(),
+            Bucket::Fixed(Duration::microseconds(500)),
+            BucketZone::Utc,
+            Reducer::Count,
+            |r| r.value,
+        )
+        .unwrap();
+        assert_eq!(aggregates.len(), 7);
+        assert_eq!(aggregates[1].start, time(5, 1, 0, 0));
+        assert_eq!(
+            aggregates[1].end,
+            DateTimeTz(time(5, 1, 0, 0).0 + Duration::microseconds(500))
+        );
+    }
+
+    #[test]
+    fn reports_bucket_ends() {
+        let aggregates = aggregate(
+            readings().iter(),
+            Bucket::Month,
+            BucketZone::Utc,
+            Reducer::Count,
+            |r| r.value,
+        )
+        .unwrap();
+        assert_eq!(
+            aggregates[2],
+            Aggregate {
+                start: time(12, 1, 0, 0),
+                end: DateTimeTz(UTC.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()),
+                count: 1,
+                value: 1.0,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_empty_fixed_buckets() {
+        for length in &[Duration::zero(), Duration::seconds(-5)] {
+            match aggregate(
+                readings().iter(),
+                Bucket::Fixed(*length),
+                BucketZone::Utc,
+                Reducer::Count,
+                |r| r.value,
+            ) {
+                Err(Error::InvalidBucket) => (),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("a bucket of length {} should be rejected", length),
+            }
+        }
+        match aggregate(
+            Vec::<Reading>::new().iter(),
+            Bucket::Fixed(Duration::zero()),
+            BucketZone::Utc,
+            Reducer::Count,
+            |r| r.value,
+        ) {
+            Err(Error::InvalidBucket) => (),
+            _ => panic!("a bucket of length zero should be rejected without any records"),
+        }
+    }
+
+    #[test]
+    fn rejects_fixed_buckets_beyond_the_range_of_times() {
+        match aggregate(
+            readings().iter(),
+            Bucket::Fixed(Duration::days(1_000_000_000)),
+            BucketZone::Utc,
+            Reducer::Count,
+            |r| r.value,
+        ) {
+            Err(Error::InvalidBucket) => (),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("a bucket that ends beyond the range of times should be rejected"),
+        }
+    }
+
+    fn zoned(readings: &[Reading], bucket: Bucket, zone: BucketZone) -> Vec<DateTimeTz> {
+        aggregate(readings.iter(), bucket, zone, Reducer::Count, |r| r.value)
+            .unwrap()
+            .into_iter()
+            .flat_map(|a| vec![a.start, a.end])
+            .collect()
+    }
+
+    fn reading(time: DateTime<Tz>) -> Reading {
+        Reading {
+            time: DateTimeTz(time),
+            value: 1.0,
+        }
+    }
+
+    #[test]
+    fn groups_by_local_days() {
+        let run = [reading(
+            Central.with_ymd_and_hms(2019, 5, 1, 23, 30, 0).unwrap(),
+        )];
+        let local_day = vec![
+            DateTimeTz(Central.with_ymd_and_hms(2019, 5, 1, 0, 0, 0).unwrap()),
+            DateTimeTz(Central.with_ymd_and_hms(2019, 5, 2, 0, 0, 0).unwrap()),
+        ];
+        assert_eq!(zoned(&run, Bucket::Day, BucketZone::Record), local_day);
+        assert_eq!(
+            zoned(&run, Bucket::Day, BucketZone::Named(Central)),
+            local_day
+        );
+        assert_eq!(
+            zoned(&run, Bucket::Day, BucketZone::Utc),
+            vec![time(5, 2, 0, 0), time(5, 3, 0, 0)]
+        );
+
+        let utc_run = [reading(UTC.with_ymd_and_hms(2019, 5, 2, 4, 30, 0).unwrap())];
+        assert_eq!(
+            zoned(&utc_run, Bucket::Day, BucketZone::Named(Central)),
+            local_day
+        );
+        assert_eq!(
+            zoned(&utc_run, Bucket::Day, BucketZone::Record),
+            vec![time(5, 2, 0, 0), time(5, 3, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn local_days_change_length_with_daylight_saving() {
+        let spring = [reading(
+            Central.with_ymd_and_hms(2019, 3, 10, 12, 0, 0).unwrap(),
+        )];
+        let bounds = zoned(&spring, Bucket::Day, BucketZone::Record);
+        assert_eq!(
+            bounds[1].0.signed_duration_since(bounds[0].0),
+            Duration::hours(23)
+        );
+
+        let fall = [reading(
+            Central.with_ymd_and_hms(2019, 11, 3, 12, 0, 0).unwrap(),
+        )];
+        let bounds = zoned(&fall, Bucket::Day, BucketZone::Record);
+        assert_eq!(
+            bounds[1].0.signed_duration_since(bounds[0].0),
+            Duration::hours(25)
+        );
+    }
+
+    #[test]
+    fn repeated_hours_stay_separate() {
+        // 01:30 happens twice in US/Central on 2019-11-03, at 06:30 and 07:30 UTC.
+        let readings = [
+            reading(UTC.with_ymd_and_hms(2019, 11, 3, 6, 30, 0).unwrap()),
+            reading(UTC.with_ymd_and_hms(2019, 11, 3, 7, 30, 0).unwrap()),
+        ];
+        let at = |h| DateTimeTz(UTC.with_ymd_and_hms(2019, 11, 3, h, 0, 0).unwrap());
+        assert_eq!(
+            zoned(&readings, Bucket::Hour, BucketZone::Named(Central)),
+            vec![at(6), at(7), at(7), at(8)]
+        );
+    }
+
+    #[test]
+    fn days_without_midnight_start_after_the_gap() {
+        // Clocks in Sao Paulo went from 00:00 straight to 01:00 on 2016-10-16.
+        let readings = [reading(
+            Sao_Paulo.with_ymd_and_hms(2016, 10, 16, 12, 0, 0).unwrap(),
+        )];
+        assert_eq!(
+            zoned(&readings, Bucket::Day, BucketZone::Record)[0],
+            DateTimeTz(UTC.with_ymd_and_hms(2016, 10, 16, 3, 0, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,266 @@
+extern crate serde;
+
+use self::serde::de::DeserializeOwned;
+use self::serde::ser::Serialize;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use criteria::Criteria;
+use options::SeriesOptions;
+use series::Series;
+use types::{Error, Record, Recordable, UniqueId};
+
+type Job<T> = Box<dyn FnOnce(&mut Series<T>) + Send>;
+
+enum Message<T: Clone + Recordable + DeserializeOwned + Serialize> {
+    Run(Job<T>),
+    Close(Completer<()>),
+}
+
+/// An asynchronous front-end to a series, for use from async code that must not block on file
+/// I/O.
+///
+/// The series lives on a worker thread of its own, which runs every operation, reads included, in
+/// the order they were started. Each operation returns a `Pending` future that resolves once the
+/// worker has run it. Clones of the handle share the same series and worker, and the worker stops
+/// once every handle has been dropped and every operation has run, or when it is closed.
+///
+/// ```text
+/// let ts: AsyncSeries<BikeTrip> = AsyncSeries::open("var/bike_trips.json").await?;
+/// let id = ts.put(trip).await?;
+/// ```
+pub struct AsyncSeries<T: Clone + Recordable + DeserializeOwned + Serialize> {
+    jobs: Mutex<Sender<Message<T>>>,
+}
+
+impl<T> AsyncSeries<T>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize + Send + 'static,
+{
+    /// Open a series on a new worker thread, as with `Series::open`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Pending<AsyncSeries<T>> {
+        AsyncSeries::open_with_options(SeriesOptions::new(), path)
+    }
+
+    /// Open a series on a new worker thread, as with `SeriesOptions::open`.
+    pub fn open_with_options<P: AsRef<Path>>(
+        options: SeriesOptions,
+        path: P,
+    ) -> Pending<AsyncSeries<T>> {
+        let path = PathBuf::from(path.as_ref());
+        let (pending, completer) = Pending::new();
+        thread::spawn(move || {
+            let mut series = match options.open(&path) {
+                Ok(series) => series,
+                Err(err) => return completer.complete(Err(err)),
+            };
+            let (jobs, queue) = channel::<Message<T>>();
+            completer.complete(Ok(AsyncSeries {
+                jobs: Mutex::new(jobs),
+            }));
+            for message in queue {
+                match message {
+                    Message::Run(job) => job(&mut series),
+                    Message::Close(completer) => {
+                        let flushed = series.flush();
+                        drop(series);
+                        return completer.complete(flushed);
+                    }
+                }
+            }
+        });
+        pending
+    }
+
+    /// Put a new record into the series, as with `Series::put`.
+    pub fn put(&self, entry: T) -> Pending<UniqueId> {
+        self.with(move |series| series.put(entry))
+    }
+
+    /// Add a new record, as with `Series::insert`.
+    pub fn insert(&self, record: Record<T>) -> Pending<()> {
+        self.with(move |series| series.insert(record))
+    }
+
+    /// Update an existing record, as with `Series::update`.
+    pub fn update(&self, record: Record<T>) -> Pending<()> {
+        self.with(move |series| series.update(record))
+    }
+
+    /// Add or update a record, as with `Series::upsert`.
+    pub fn upsert(&self, record: Record<T>) -> Pending<()> {
+        self.with(move |series| series.upsert(record))
+    }
+
+    /// Update a record if it is still at the expected revision, as with
+    /// `Series::compare_and_update`.
+    pub fn compare_and_update(&self, record: Record<T>, expected_revision: u64) -> Pending<()> {
+        self.with(move |series| series.compare_and_update(record, expected_revision))
+    }
+
+    /// Delete a record, as with `Series::delete`.
+    pub fn delete(&self, uuid: &UniqueId) -> Pending<bool> {
+        let uuid = uuid.clone();
+        self.with(move |series| series.delete(&uuid))
+    }
+
+    /// Get an exact record based on unique id, as with `Series::get`.
+    pub fn get(&self, uuid: &UniqueId) -> Pending<Option<Record<T>>> {
+        let uuid = uuid.clone();
+        self.with(move |series| series.get(&uuid))
+    }
+
+    /// Get all of the records, in time order, as with `Series::all_records`.
+    pub fn all_records(&self) -> Pending<Vec<Record<T>>> {
+        self.with(|series| series.all_records())
+    }
+
+    /// Search the records, as with `Series::search`.
+    pub fn search<C>(&self, criteria: C) -> Pending<Vec<Record<T>>>
+    where
+        C: Criteria + Send + 'static,
+    {
+        self.with(move |series| series.search(criteria))
+    }
+
+    /// Run any other operation against the series on the worker thread.
+    pub fn with<R, F>(&self, f: F) -> Pending<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Series<T>) -> Result<R, Error> + Send + 'static,
+    {
+        let (pending, completer) = Pending::new();
+        let job: Job<T> = Box::new(move |series| completer.complete(f(series)));
+        self.send(Message::Run(job));
+        pending
+    }
+
+    /// Close the series once every operation started before this one has run, for every clone of
+    /// this handle. The future resolves once the series file has been flushed and closed, and
+    /// any operation started after this one fails.
+    pub fn close(self) -> Pending<()> {
+        let (pending, completer) = Pending::new();
+        self.send(Message::Close(completer));
+        pending
+    }
+
+    fn send(&self, message: Message<T>) {
+        // If the worker is gone, the message is dropped along with its completer, which resolves
+        // the future with an error.
+        let _ = self
+            .jobs
+            .lock()
+            .expect("a thread panicked while starting an operation")
+            .send(message);
+    }
+
+    /// Flush and sync every write made so far, as with `Series::sync`.
+    pub fn sync(&self) -> Pending<()> {
+        self.with(|series| series.sync())
+    }
+}
+
+impl<T> Clone for AsyncSeries<T>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+{
+    fn clone(&self) -> AsyncSeries<T> {
+        let jobs = self
+            .jobs
+            .lock()
+            .expect("a thread panicked while starting an operation")
+            .clone();
+        AsyncSeries {
+            jobs: Mutex::new(jobs),
+        }
+    }
+}
+
+struct Slot<R> {
+    result: Option<Result<R, Error>>,
+    waker: Option<Waker>,
+}
+
+/// The result of an operation on an `AsyncSeries`, which resolves once the worker thread has run
+/// the operation.
+pub struct Pending<R> {
+    slot: Arc<Mutex<Slot<R>>>,
+}
+
+impl<R> Pending<R> {
+    fn new() -> (Pending<R>, Completer<R>) {
+        let slot = Arc::new(Mutex::new(Slot {
+            result: None,
+            waker: None,
+        }));
+        (
+            Pending { slot: slot.clone() },
+            Completer {
+                slot,
+                completed: false,
+            },
+        )
+    }
+}
+
+impl<R> Future for Pending<R> {
+    type Output = Result<R, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<R, Error>> {
+        let mut slot = self
+            .slot
+            .lock()
+            .expect("the worker thread panicked while completing an operation");
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The worker thread's end of a `Pending`. Dropping it without completing it, as happens when an
+/// operation panics, resolves the future with an error rather than leaving it pending forever.
+struct Completer<R> {
+    slot: Arc<Mutex<Slot<R>>>,
+    completed: bool,
+}
+
+impl<R> Completer<R> {
+    fn complete(mut self, result: Result<R, Error>) {
+        self.resolve(result);
+        self.completed = true;
+    }
+
+    fn resolve(&self, result: Result<R, Error>) {
+        let waker = match self.slot.lock() {
+            Ok(mut slot) => {
+                slot.result = Some(result);
+                slot.waker.take()
+            }
+            Err(_) => None,
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<R> Drop for Completer<R> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.resolve(Err(Error::IOError(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the series worker thread stopped before running the operation",
+            ))));
+        }
+    }
+}
@@ -0,0 +1,123 @@
+extern crate serde;
+
+use self::serde::de::DeserializeOwned;
+use self::serde::ser::Serialize;
+use std::collections::HashMap;
+
+use series::Series;
+use types::{DeletableRecord, Error, Record, Recordable, UniqueId};
+
+/// A set of changes to a series that are written together, as a single line of the file, when
+/// the batch is committed. After a crash, either every change in the batch or none of them is
+/// in the series when it is reopened.
+///
+/// Changes are checked against the series, and the changes staged before them, as they are
+/// staged, but nothing in the series changes until `commit`. Dropping a batch without committing
+/// it discards every staged change.
+///
+/// ```text
+/// let mut batch = ts.batch();
+/// for reading in readings {
+///     batch.put(reading);
+/// }
+/// batch.commit()?;
+/// ```
+pub struct Batch<'s, T: Clone + Recordable + DeserializeOwned + Serialize> {
+    series: &'s mut Series<T>,
+    records: Vec<DeletableRecord<T>>,
+
+    /// Whether each id touched by the batch will be a live record once it is committed.
+    live: HashMap<UniqueId, bool>,
+}
+
+impl<'s, T> Batch<'s, T>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+{
+    pub(crate) fn new(series: &'s mut Series<T>) -> Batch<'s, T> {
+        Batch {
+            series,
+            records: Vec::new(),
+            live: HashMap::new(),
+        }
+    }
+
+    fn is_live(&self, uuid: &UniqueId) -> bool {
+        match self.live.get(uuid) {
+            Some(live) => *live,
+            None => self.series.contains(uuid),
+        }
+    }
+
+    fn stage(&mut self, uuid: UniqueId, data: Option<T>) {
+        self.live.insert(uuid.clone(), data.is_some());
+        self.records.push(DeletableRecord {
+            id: uuid,
+            data,
+            created_at: None,
+            updated_at: None,
+            revision: 0,
+        });
+    }
+
+    /// Stage a new record. A unique id will be assigned to the record and returned.
+    pub fn put(&mut self, entry: T) -> UniqueId {
+        let uuid = UniqueId::new();
+        self.stage(uuid.clone(), Some(entry));
+        uuid
+    }
+
+    /// Stage a new record, as with `Series::insert`. If a record with the same `UniqueId` is
+    /// already live, nothing is staged and this returns `Error::AlreadyExists`.
+    pub fn insert(&mut self, record: Record<T>) -> Result<(), Error> {
+        if self.is_live(&record.id) {
+            return Err(Error::AlreadyExists(record.id));
+        }
+        self.stage(record.id, Some(record.data));
+        Ok(())
+    }
+
+    /// Stage an update to an existing record, as with `Series::update`. If no record with the
+    /// same `UniqueId` is live, nothing is staged and this returns `Error::NotFound`.
+    pub fn update(&mut self, record: Record<T>) -> Result<(), Error> {
+        if !self.is_live(&record.id) {
+            return Err(Error::NotFound(record.id));
+        }
+        self.stage(record.id, Some(record.data));
+        Ok(())
+    }
+
+    /// Stage a record to be added, or updated if it is already live.
+    pub fn upsert(&mut self, record: Record<T>) {
+        self.stage(record.id, Some(record.data));
+    }
+
+    /// Stage the deletion of a record, returning whether there is a live record to delete.
+    /// Nothing is staged if there is not.
+    pub fn delete(&mut self, uuid: &UniqueId) -> bool {
+        if !self.is_live(uuid) {
+            return false;
+        }
+        self.stage(uuid.clone(), None);
+        true
+    }
+
+    /// The number of changes staged so far.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Whether no changes have been staged.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Apply every staged change to the series and write them all to the file as one line. An
+    /// empty batch writes nothing.
+    pub fn commit(self) -> Result<(), Error> {
+        self.series.write_batch(self.records)
+    }
+
+    /// Discard every staged change. This is the same as dropping the batch.
+    pub fn rollback(self) {}
+}
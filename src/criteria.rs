@@ -1,4 +1,12 @@
+extern crate serde;
+extern crate serde_json;
+
+use self::serde::ser::Serialize;
+use self::serde_json::Value;
 use date_time_tz::DateTimeTz;
+use interval::Interval;
+use std::cmp::Ordering;
+use std::ops;
 use types::Recordable;
 
 /// This trait is used for constructing queries for searching the database.
@@ -6,11 +14,58 @@ pub trait Criteria {
     /// Apply this criteria element to a record, returning true only if the record matches the
     /// criteria.
     fn apply<T: Recordable>(&self, record: &T) -> bool;
+
+    /// The range of timestamps outside of which this criteria can never match. A series uses this
+    /// to look up candidate records in its time index before applying the criteria to each of
+    /// them, so it must never exclude a record that `apply` would accept. The default is
+    /// unbounded, which is always correct, but forces a scan of every record.
+    fn time_bounds(&self) -> Interval<DateTimeTz> {
+        Interval::unbounded()
+    }
+
+    /// Tags that every record matching this criteria must carry. A series uses this to look up
+    /// candidate records in its tag index, so, as with `time_bounds`, it must never exclude a
+    /// record that `apply` would accept. The default is no tags at all.
+    fn required_tags(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Combine with another criteria, matching only records that match both. `other` may also be
+    /// payload criteria, such as a `Field`, and the combination is then payload criteria too.
+    fn and<C>(self, other: C) -> And<Self, C>
+    where
+        Self: Sized,
+    {
+        And {
+            lside: self,
+            rside: other,
+        }
+    }
+
+    /// Combine with another criteria, matching records that match either. As with `and`, `other`
+    /// may also be payload criteria.
+    fn or<C>(self, other: C) -> Or<Self, C>
+    where
+        Self: Sized,
+    {
+        Or {
+            lside: self,
+            rside: other,
+        }
+    }
+
+    /// Invert this criteria, matching exactly the records it does not.
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not { criteria: self }
+    }
 }
 
 
 /// Specify two criteria that must both be matched.
-pub struct And<A: Criteria, B: Criteria> {
+pub struct And<A, B> {
     pub lside: A,
     pub rside: B,
 }
@@ -24,16 +79,104 @@ where
     fn apply<T: Recordable>(&self, record: &T) -> bool {
         self.lside.apply(record) && self.rside.apply(record)
     }
+
+    fn time_bounds(&self) -> Interval<DateTimeTz> {
+        self.lside
+            .time_bounds()
+            .intersection(&self.rside.time_bounds())
+    }
+
+    fn required_tags(&self) -> Vec<String> {
+        let mut tags = self.lside.required_tags();
+        for tag in self.rside.required_tags() {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        tags
+    }
+}
+
+
+impl<D, A, B> PayloadCriteria<D> for And<A, B>
+where
+    A: PayloadCriteria<D>,
+    B: PayloadCriteria<D>,
+{
+    fn apply_payload(&self, data: &D) -> bool {
+        self.lside.apply_payload(data) && self.rside.apply_payload(data)
+    }
 }
 
 
 /// Specify two criteria, either of which may be matched.
-pub struct Or<A: Criteria, B: Criteria> {
+pub struct Or<A, B> {
     pub lside: A,
     pub rside: B,
 }
 
 
+impl<A, B> Criteria for Or<A, B>
+where
+    A: Criteria,
+    B: Criteria,
+{
+    fn apply<T: Recordable>(&self, record: &T) -> bool {
+        self.lside.apply(record) || self.rside.apply(record)
+    }
+
+    fn time_bounds(&self) -> Interval<DateTimeTz> {
+        self.lside.time_bounds().hull(&self.rside.time_bounds())
+    }
+
+    fn required_tags(&self) -> Vec<String> {
+        let rtags = self.rside.required_tags();
+        self.lside
+            .required_tags()
+            .into_iter()
+            .filter(|tag| rtags.contains(tag))
+            .collect()
+    }
+}
+
+
+impl<D, A, B> PayloadCriteria<D> for Or<A, B>
+where
+    A: PayloadCriteria<D>,
+    B: PayloadCriteria<D>,
+{
+    fn apply_payload(&self, data: &D) -> bool {
+        self.lside.apply_payload(data) || self.rside.apply_payload(data)
+    }
+}
+
+
+/// Specify a criteria that must not be matched.
+pub struct Not<A> {
+    pub criteria: A,
+}
+
+
+impl<A> Criteria for Not<A>
+where
+    A: Criteria,
+{
+    fn apply<T: Recordable>(&self, record: &T) -> bool {
+        !self.criteria.apply(record)
+    }
+}
+
+
+impl<D, A> PayloadCriteria<D> for Not<A>
+where
+    A: PayloadCriteria<D>,
+{
+    fn apply_payload(&self, data: &D) -> bool {
+        !self.criteria.apply_payload(data)
+    }
+}
+
+
 /// Specify the starting time for a search. This consists of a UTC timestamp and a specifier as to
 /// whether the exact time is included in the search criteria.
 pub struct StartTime {
@@ -50,6 +193,17 @@ impl Criteria for StartTime {
             record.timestamp() > self.time
         }
     }
+
+    fn time_bounds(&self) -> Interval<DateTimeTz> {
+        Interval::starting(self.time.clone(), self.incl)
+    }
+}
+
+
+impl<D: Recordable> PayloadCriteria<D> for StartTime {
+    fn apply_payload(&self, data: &D) -> bool {
+        self.apply(data)
+    }
 }
 
 
@@ -69,6 +223,17 @@ impl Criteria for EndTime {
             record.timestamp() < self.time
         }
     }
+
+    fn time_bounds(&self) -> Interval<DateTimeTz> {
+        Interval::ending(self.time.clone(), self.incl)
+    }
+}
+
+
+impl<D: Recordable> PayloadCriteria<D> for EndTime {
+    fn apply_payload(&self, data: &D) -> bool {
+        self.apply(data)
+    }
 }
 
 
@@ -84,36 +249,328 @@ impl Criteria for Tags {
             .iter()
             .all(|v| record_tags.contains(v))
     }
+
+    fn required_tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
 }
 
 
-/// Specify a criteria that searches for records matching an exact time.
-pub fn exact_time(time: DateTimeTz) -> And<StartTime, EndTime> {
-    And {
-        lside: StartTime {
-            time: time.clone(),
-            incl: true,
-        },
-        rside: EndTime { time, incl: true },
+impl<D: Recordable> PayloadCriteria<D> for Tags {
+    fn apply_payload(&self, data: &D) -> bool {
+        self.apply(data)
     }
 }
 
 
+/// Criteria over the data of the records of a series that holds `D`, for conditions on the
+/// payload that `Criteria` cannot express. Pass them to `Series::search_where` and its relatives
+/// along with the criteria that narrow down the search.
+///
+/// Any `Fn(&D) -> bool` closure is payload criteria, and so is a `Field`. Every criteria in this
+/// module is payload criteria as well, so `And`, `Or` and `Not` combine the two freely:
+///
+/// ```text
+/// let criteria = Tags { tags: vec![String::from("commute")] }
+///     .or(Field::new("/distance", Comparison::GreaterThan, 20000.0));
+/// ```
+pub trait PayloadCriteria<D> {
+    /// Apply this criteria to the data of a record, returning true only if the data matches.
+    fn apply_payload(&self, data: &D) -> bool;
+}
+
+
+impl<D, F> PayloadCriteria<D> for F
+where
+    F: Fn(&D) -> bool,
+{
+    fn apply_payload(&self, data: &D) -> bool {
+        self(data)
+    }
+}
+
+
+/// The ways in which `Field` can compare a value in a record against its operand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+}
+
+
+impl Comparison {
+    /// Compare two JSON values. Numbers compare numerically and strings lexically; any other
+    /// values, or values of different types, can only be equal or not equal.
+    fn compare(self, lside: &Value, rside: &Value) -> bool {
+        let ordering = match (lside, rside) {
+            (Value::Number(l), Value::Number(r)) => match (l.as_f64(), r.as_f64()) {
+                (Some(l), Some(r)) => l.partial_cmp(&r),
+                _ => None,
+            },
+            (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+            _ => None,
+        };
+        match (self, ordering) {
+            (Comparison::Equal, Some(ordering)) => ordering == Ordering::Equal,
+            (Comparison::NotEqual, Some(ordering)) => ordering != Ordering::Equal,
+            (Comparison::Equal, None) => lside == rside,
+            (Comparison::NotEqual, None) => lside != rside,
+            (_, None) => false,
+            (Comparison::LessThan, Some(ordering)) => ordering == Ordering::Less,
+            (Comparison::LessOrEqual, Some(ordering)) => ordering != Ordering::Greater,
+            (Comparison::GreaterThan, Some(ordering)) => ordering == Ordering::Greater,
+            (Comparison::GreaterOrEqual, Some(ordering)) => ordering != Ordering::Less,
+        }
+    }
+}
+
+
+/// Specify a comparison against one field of the data of a record. The record's data is
+/// serialized to JSON and the field is looked up with a JSON pointer (RFC 6901), such as
+/// `"/distance"` or `"/route/0/name"`. Records that do not have the field never match.
+///
+/// ```text
+/// let long_trips = Field::new("/distance", Comparison::GreaterThan, 20000.0);
+/// ```
+pub struct Field {
+    pub pointer: String,
+    pub comparison: Comparison,
+    pub value: Value,
+}
+
+
+impl Field {
+    pub fn new<V: Into<Value>>(pointer: &str, comparison: Comparison, value: V) -> Field {
+        Field {
+            pointer: String::from(pointer),
+            comparison,
+            value: value.into(),
+        }
+    }
+
+    /// Combine with other payload criteria, matching only data that matches both.
+    pub fn and<P>(self, other: P) -> And<Field, P> {
+        And {
+            lside: self,
+            rside: other,
+        }
+    }
+
+    /// Combine with other payload criteria, matching data that matches either.
+    pub fn or<P>(self, other: P) -> Or<Field, P> {
+        Or {
+            lside: self,
+            rside: other,
+        }
+    }
+}
+
+
+/// Invert a `Field` with `!`, matching exactly the data it does not. Unlike the opposite
+/// comparison, this matches data that does not have the field.
+impl ops::Not for Field {
+    type Output = Not<Field>;
+
+    fn not(self) -> Not<Field> {
+        Not { criteria: self }
+    }
+}
+
+
+impl<D> PayloadCriteria<D> for Field
+where
+    D: Serialize,
+{
+    fn apply_payload(&self, data: &D) -> bool {
+        match serde_json::to_value(data) {
+            Ok(value) => match value.pointer(&self.pointer) {
+                Some(field) => self.comparison.compare(field, &self.value),
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+
+impl Criteria for Interval<DateTimeTz> {
+    fn apply<T: Recordable>(&self, record: &T) -> bool {
+        self.contains(&record.timestamp())
+    }
+
+    fn time_bounds(&self) -> Interval<DateTimeTz> {
+        self.clone()
+    }
+}
+
+
+impl<D: Recordable> PayloadCriteria<D> for Interval<DateTimeTz> {
+    fn apply_payload(&self, data: &D) -> bool {
+        self.apply(data)
+    }
+}
+
+
+/// Specify a criteria that searches for records matching an exact time.
+pub fn exact_time(time: DateTimeTz) -> Interval<DateTimeTz> {
+    Interval::exact(time)
+}
+
+
 /// Specify a criteria that searches for all records within a time range.
 pub fn time_range(
     start: DateTimeTz,
     start_incl: bool,
     end: DateTimeTz,
     end_incl: bool,
-) -> And<StartTime, EndTime> {
-    And {
-        lside: StartTime {
-            time: start,
-            incl: start_incl,
-        },
-        rside: EndTime {
-            time: end,
-            incl: end_incl,
-        },
+) -> Interval<DateTimeTz> {
+    Interval::new(start, start_incl, end, end_incl)
+}
+
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use chrono_tz::Etc::UTC;
+    use std::ops::Bound;
+
+    use super::*;
+
+    #[derive(Clone, Serialize)]
+    struct Entry {
+        time: DateTimeTz,
+        tags: Vec<String>,
+    }
+
+    impl Recordable for Entry {
+        fn timestamp(&self) -> DateTimeTz {
+            self.time.clone()
+        }
+
+        fn tags(&self) -> Vec<String> {
+            self.tags.clone()
+        }
+    }
+
+    fn day(d: u32) -> DateTimeTz {
+        DateTimeTz(UTC.with_ymd_and_hms(2019, 5, d, 0, 0, 0).unwrap())
+    }
+
+    fn entry(d: u32, tags: &[&str]) -> Entry {
+        Entry {
+            time: day(d),
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+        }
+    }
+
+    fn tags(tags: &[&str]) -> Tags {
+        Tags {
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+        }
+    }
+
+    #[test]
+    fn or_matches_either_side() {
+        let criteria = exact_time(day(1)).or(exact_time(day(3)));
+        assert!(criteria.apply(&entry(1, &[])));
+        assert!(!criteria.apply(&entry(2, &[])));
+        assert!(criteria.apply(&entry(3, &[])));
+        assert_eq!(
+            criteria.time_bounds(),
+            Interval::from_bounds(Bound::Included(day(1)), Bound::Included(day(3)))
+        );
+    }
+
+    #[test]
+    fn or_requires_only_common_tags() {
+        let criteria = tags(&["bike", "commute"]).or(tags(&["bike", "race"]));
+        assert_eq!(criteria.required_tags(), vec![String::from("bike")]);
+        assert!(criteria.apply(&entry(1, &["bike", "race"])));
+        assert!(!criteria.apply(&entry(1, &["bike"])));
+    }
+
+    #[test]
+    fn not_inverts_and_constrains_nothing() {
+        let criteria = tags(&["race"]).not();
+        assert!(criteria.apply(&entry(1, &["commute"])));
+        assert!(!criteria.apply(&entry(1, &["race"])));
+        assert!(criteria.required_tags().is_empty());
+        assert_eq!(criteria.time_bounds(), Interval::unbounded());
+    }
+
+    #[test]
+    fn combinators_compose() {
+        let criteria = time_range(day(2), true, day(4), false)
+            .and(tags(&["commute"]))
+            .and(tags(&["race"]).not());
+        assert!(!criteria.apply(&entry(1, &["commute"])));
+        assert!(criteria.apply(&entry(2, &["commute"])));
+        assert!(!criteria.apply(&entry(3, &["commute", "race"])));
+        assert!(!criteria.apply(&entry(4, &["commute"])));
+        assert_eq!(
+            criteria.time_bounds(),
+            Interval::from_bounds(Bound::Included(day(2)), Bound::Excluded(day(4)))
+        );
+        assert_eq!(criteria.required_tags(), vec![String::from("commute")]);
+    }
+
+    #[test]
+    fn and_takes_the_tighter_bounds() {
+        let criteria = StartTime {
+            time: day(2),
+            incl: true,
+        }
+        .and(StartTime {
+            time: day(2),
+            incl: false,
+        })
+        .and(EndTime {
+            time: day(5),
+            incl: true,
+        });
+        assert_eq!(
+            criteria.time_bounds(),
+            Interval::from_bounds(Bound::Excluded(day(2)), Bound::Included(day(5)))
+        );
+    }
+
+    #[test]
+    fn fields_compare_the_serialized_payload() {
+        let criteria = Field::new("/1", Comparison::Equal, "race");
+        assert!(criteria.apply_payload(&vec!["bike", "race"]));
+        assert!(!criteria.apply_payload(&vec!["bike", "commute"]));
+        assert!(!criteria.apply_payload(&vec!["bike"]));
+    }
+
+    #[test]
+    fn criteria_and_fields_combine() {
+        let criteria = tags(&["race"]).or(Field::new("/tags/0", Comparison::Equal, "bike"));
+        assert!(criteria.apply_payload(&entry(1, &["race"])));
+        assert!(criteria.apply_payload(&entry(1, &["bike"])));
+        assert!(!criteria.apply_payload(&entry(1, &["commute"])));
+
+        let criteria = exact_time(day(1)).and(!Field::new("/tags/0", Comparison::Equal, "race"));
+        assert!(criteria.apply_payload(&entry(1, &["commute"])));
+        assert!(!criteria.apply_payload(&entry(1, &["race"])));
+        assert!(!criteria.apply_payload(&entry(2, &["commute"])));
+    }
+
+    #[test]
+    fn comparisons_order_numbers_and_strings() {
+        let (one, two) = (Value::from(1), Value::from(2.5));
+        assert!(Comparison::LessThan.compare(&one, &two));
+        assert!(Comparison::LessOrEqual.compare(&one, &one));
+        assert!(Comparison::GreaterOrEqual.compare(&two, &one));
+        assert!(Comparison::Equal.compare(&Value::from(1.0), &one));
+        assert!(Comparison::GreaterThan.compare(&Value::from("b"), &Value::from("a")));
+
+        let flag = Value::from(true);
+        assert!(Comparison::Equal.compare(&flag, &flag));
+        assert!(Comparison::NotEqual.compare(&flag, &one));
+        assert!(!Comparison::LessThan.compare(&flag, &one));
     }
 }
@@ -6,6 +6,7 @@ use chrono_tz::Etc::UTC;
 use serde::de::{self, Deserialize, Deserializer, Visitor};
 use serde::ser::{Serialize, Serializer};
 use std::fmt;
+use std::str;
 
 /// This is a wrapper around date time objects, using timezones from the chroon-tz database and
 /// providing string representation and parsing of the form "<RFC3339> <Timezone Name>", i.e.,
@@ -29,11 +30,25 @@ impl DateTimeTz {
         DateTimeTz(f(self.0))
     }
 
-    pub fn to_string(&self) -> String {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Result<DateTimeTz, chrono::ParseError> {
+        let v: Vec<&str> = s.split_terminator(" ").collect();
+        if v.len() == 2 {
+            let tz = v[1].parse::<chrono_tz::Tz>().unwrap();
+            chrono::DateTime::parse_from_rfc3339(v[0]).map(|ts| DateTimeTz(ts.with_timezone(&tz)))
+        } else {
+            chrono::DateTime::parse_from_rfc3339(v[0]).map(|ts| DateTimeTz(ts.with_timezone(&UTC)))
+        }
+    }
+}
+
+impl fmt::Display for DateTimeTz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.0.timezone() == UTC {
-            self.0.to_rfc3339_opts(SecondsFormat::Secs, true)
+            write!(f, "{}", self.0.to_rfc3339_opts(SecondsFormat::Secs, true))
         } else {
-            format!(
+            write!(
+                f,
                 "{} {}",
                 self.0.with_timezone(&chrono_tz::Etc::UTC).to_rfc3339_opts(
                     SecondsFormat::Secs,
@@ -43,15 +58,13 @@ impl DateTimeTz {
             )
         }
     }
+}
 
-    pub fn from_str(s: &str) -> Result<DateTimeTz, chrono::ParseError> {
-        let v: Vec<&str> = s.split_terminator(" ").collect();
-        if v.len() == 2 {
-            let tz = v[1].parse::<chrono_tz::Tz>().unwrap();
-            chrono::DateTime::parse_from_rfc3339(v[0]).map(|ts| DateTimeTz(ts.with_timezone(&tz)))
-        } else {
-            chrono::DateTime::parse_from_rfc3339(v[0]).map(|ts| DateTimeTz(ts.with_timezone(&UTC)))
-        }
+impl str::FromStr for DateTimeTz {
+    type Err = chrono::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DateTimeTz::from_str(s)
     }
 }
 
@@ -66,7 +79,7 @@ impl<'de> Visitor<'de> for DateTimeTzVisitor {
 
     fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
         DateTimeTz::from_str(s).or(Err(E::custom(
-            format!("string is not a parsable datetime representation"),
+            "string is not a parsable datetime representation",
         )))
     }
 }
@@ -95,28 +108,28 @@ mod test {
 
     #[test]
     fn it_creates_timestamp_with_z() {
-        let t = DateTimeTz(UTC.ymd(2019, 5, 15).and_hms(12, 0, 0));
+        let t = DateTimeTz(UTC.with_ymd_and_hms(2019, 5, 15, 12, 0, 0).unwrap());
         assert_eq!(t.to_string(), "2019-05-15T12:00:00Z");
     }
 
     #[test]
     fn it_parses_utc_rfc3339_z() {
         let t = DateTimeTz::from_str("2019-05-15T12:00:00Z").unwrap();
-        assert_eq!(t, DateTimeTz(UTC.ymd(2019, 5, 15).and_hms(12, 0, 0)));
+        assert_eq!(t, DateTimeTz(UTC.with_ymd_and_hms(2019, 5, 15, 12, 0, 0).unwrap()));
     }
 
     #[test]
     fn it_parses_rfc3339_with_offset() {
         let t = DateTimeTz::from_str("2019-05-15T12:00:00-06:00").unwrap();
-        assert_eq!(t, DateTimeTz(UTC.ymd(2019, 5, 15).and_hms(18, 0, 0)));
+        assert_eq!(t, DateTimeTz(UTC.with_ymd_and_hms(2019, 5, 15, 18, 0, 0).unwrap()));
     }
 
     #[test]
     fn it_parses_rfc3339_with_tz() {
         let t = DateTimeTz::from_str("2019-06-15T19:00:00Z US/Arizona").unwrap();
-        assert_eq!(t, DateTimeTz(UTC.ymd(2019, 6, 15).and_hms(19, 0, 0)));
-        assert_eq!(t, DateTimeTz(Arizona.ymd(2019, 6, 15).and_hms(12, 0, 0)));
-        assert_eq!(t, DateTimeTz(Central.ymd(2019, 6, 15).and_hms(14, 0, 0)));
+        assert_eq!(t, DateTimeTz(UTC.with_ymd_and_hms(2019, 6, 15, 19, 0, 0).unwrap()));
+        assert_eq!(t, DateTimeTz(Arizona.with_ymd_and_hms(2019, 6, 15, 12, 0, 0).unwrap()));
+        assert_eq!(t, DateTimeTz(Central.with_ymd_and_hms(2019, 6, 15, 14, 0, 0).unwrap()));
         assert_eq!(t.to_string(), "2019-06-15T19:00:00Z US/Arizona");
     }
 
@@ -150,6 +163,6 @@ mod test {
     fn it_json_parses() {
         let t = serde_json::from_str::<DateTimeTz>("\"2019-06-15T19:00:00Z America/Phoenix\"")
             .unwrap();
-        assert_eq!(t, DateTimeTz(Phoenix.ymd(2019, 6, 15).and_hms(12, 0, 0)));
+        assert_eq!(t, DateTimeTz(Phoenix.with_ymd_and_hms(2019, 6, 15, 12, 0, 0).unwrap()));
     }
 }
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use criteria::{Criteria, PayloadCriteria};
+use date_time_tz::DateTimeTz;
+use index::Index;
+use types::{Record, Recordable, UniqueId};
+
+/// One version of a record, as written to the series file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Version<T> {
+    /// The position of the line in the file, counting from zero at the first line. The records of
+    /// a batch share a line.
+    pub write: usize,
+
+    /// When the line was written, to the second. Lines written by older versions of this library
+    /// carry no such stamp.
+    pub written_at: Option<DateTimeTz>,
+
+    /// The revision the line was written at, or 0 for a line with no stamp.
+    pub revision: u64,
+
+    /// The data of the record, or `None` if this version is a deletion.
+    pub data: Option<T>,
+}
+
+/// A point in the history of a series to reconstruct it at.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AsOf {
+    /// Just after the given number of lines had been written to the file.
+    Write(usize),
+
+    /// At the given wall-clock time. Lines written within the same second as this time are
+    /// included, and so are lines with no stamp at all.
+    Time(DateTimeTz),
+}
+
+impl AsOf {
+    pub(crate) fn includes(&self, write: usize, written_at: Option<&DateTimeTz>) -> bool {
+        match (self, written_at) {
+            (AsOf::Write(count), _) => write < *count,
+            (AsOf::Time(time), Some(written_at)) => written_at <= time,
+            (AsOf::Time(_), None) => true,
+        }
+    }
+}
+
+/// The records of a series as they were at some point: reconstructed from its file by
+/// `Series::as_of`, or as last written through a `SharedSeries`. Nothing written to the series
+/// afterwards changes a snapshot, and cloning one is cheap.
+pub struct Snapshot<T: Clone + Recordable> {
+    records: Arc<Index<T>>,
+}
+
+impl<T> Snapshot<T>
+where
+    T: Clone + Recordable,
+{
+    pub(crate) fn new(records: Arc<Index<T>>) -> Snapshot<T> {
+        Snapshot { records }
+    }
+
+    /// Get all of the records in the snapshot, in time order.
+    pub fn all_records(&self) -> Vec<Record<T>> {
+        self.records.values().cloned().collect()
+    }
+
+    /// Iterate over all of the records in the snapshot, in time order.
+    pub fn records<'s>(&'s self) -> impl Iterator<Item = &'s Record<T>> + 's {
+        self.records.values()
+    }
+
+    /// Perform a search on the records in the snapshot, in the same way as `Series::search`.
+    pub fn search<C>(&self, criteria: C) -> Vec<Record<T>>
+    where
+        C: Criteria,
+    {
+        self.records
+            .search(criteria.time_bounds(), &criteria.required_tags())
+            .filter(|&tr| criteria.apply(tr))
+            .cloned()
+            .collect()
+    }
+
+    /// Perform a search on the records in the snapshot, in the same way as `Series::search_where`.
+    pub fn search_where<C, P>(&self, criteria: C, payload: P) -> Vec<Record<T>>
+    where
+        C: Criteria,
+        P: PayloadCriteria<T>,
+    {
+        self.records
+            .search(criteria.time_bounds(), &criteria.required_tags())
+            .filter(|&tr| criteria.apply(tr) && payload.apply_payload(&tr.data))
+            .cloned()
+            .collect()
+    }
+
+    /// Get an exact record from the snapshot based on unique id.
+    pub fn get(&self, uuid: &UniqueId) -> Option<Record<T>> {
+        self.records.get(uuid).cloned()
+    }
+}
+
+impl<T> Clone for Snapshot<T>
+where
+    T: Clone + Recordable,
+{
+    fn clone(&self) -> Snapshot<T> {
+        Snapshot {
+            records: Arc::clone(&self.records),
+        }
+    }
+}
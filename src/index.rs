@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use date_time_tz::DateTimeTz;
+use interval::Interval;
+use types::{Record, Recordable, UniqueId};
+
+/// The live records of a series, along with the secondary indexes that let searches avoid
+/// scanning every record.
+#[derive(Clone)]
+pub(crate) struct Index<T: Clone + Recordable> {
+    records: HashMap<UniqueId, Record<T>>,
+    by_time: BTreeMap<DateTimeTz, BTreeSet<UniqueId>>,
+    by_tag: HashMap<String, HashSet<UniqueId>>,
+}
+
+impl<T> Index<T>
+where
+    T: Clone + Recordable,
+{
+    pub(crate) fn new() -> Index<T> {
+        Index {
+            records: HashMap::new(),
+            by_time: BTreeMap::new(),
+            by_tag: HashMap::new(),
+        }
+    }
+
+    /// Add a record, replacing and returning any record that already had the same id.
+    pub(crate) fn insert(&mut self, record: Record<T>) -> Option<Record<T>> {
+        let previous = self.remove(&record.id);
+        self.by_time
+            .entry(record.timestamp())
+            .or_default()
+            .insert(record.id.clone());
+        for tag in record.tags() {
+            self.by_tag
+                .entry(tag)
+                .or_default()
+                .insert(record.id.clone());
+        }
+        self.records.insert(record.id.clone(), record);
+        previous
+    }
+
+    /// Remove and return the record with the given id.
+    pub(crate) fn remove(&mut self, id: &UniqueId) -> Option<Record<T>> {
+        let record = self.records.remove(id)?;
+        let timestamp = record.timestamp();
+        let now_empty = match self.by_time.get_mut(&timestamp) {
+            Some(ids) => {
+                ids.remove(id);
+                ids.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            self.by_time.remove(&timestamp);
+        }
+        for tag in record.tags() {
+            let now_empty = match self.by_tag.get_mut(&tag) {
+                Some(ids) => {
+                    ids.remove(id);
+                    ids.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.by_tag.remove(&tag);
+            }
+        }
+        Some(record)
+    }
+
+    pub(crate) fn get(&self, id: &UniqueId) -> Option<&Record<T>> {
+        self.records.get(id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Every record, in time order.
+    pub(crate) fn values<'s>(&'s self) -> impl Iterator<Item = &'s Record<T>> + 's {
+        self.range(Interval::unbounded())
+    }
+
+    /// The records whose timestamps fall within the bounds, in time order. Records with the same
+    /// timestamp come out in order of their ids.
+    pub(crate) fn range<'s>(
+        &'s self,
+        bounds: Interval<DateTimeTz>,
+    ) -> Box<dyn Iterator<Item = &'s Record<T>> + 's> {
+        // BTreeMap::range panics on empty bounds rather than returning nothing.
+        if bounds.is_empty() {
+            return Box::new(None.into_iter());
+        }
+        let records = &self.records;
+        Box::new(
+            self.by_time
+                .range((bounds.start, bounds.end))
+                .flat_map(|(_, ids)| ids.iter())
+                .map(move |id| &records[id]),
+        )
+    }
+
+    /// The records whose timestamps fall within the bounds and which carry every one of the tags,
+    /// in time order.
+    ///
+    /// With no tags this is the same as `range`. Otherwise only the records under the rarest of
+    /// the tags are examined.
+    pub(crate) fn search<'s>(
+        &'s self,
+        bounds: Interval<DateTimeTz>,
+        tags: &[String],
+    ) -> Box<dyn Iterator<Item = &'s Record<T>> + 's> {
+        if tags.is_empty() {
+            return self.range(bounds);
+        }
+
+        let mut postings = Vec::with_capacity(tags.len());
+        for tag in tags {
+            match self.by_tag.get(tag) {
+                Some(ids) => postings.push(ids),
+                None => return Box::new(None.into_iter()),
+            }
+        }
+        postings.sort_by_key(|ids| ids.len());
+
+        let mut candidates: Vec<&Record<T>> = postings[0]
+            .iter()
+            .filter(|id| postings[1..].iter().all(|ids| ids.contains(id)))
+            .map(|id| &self.records[id])
+            .filter(|record| bounds.contains(&record.timestamp()))
+            .collect();
+        candidates.sort_by(|l, r| {
+            l.timestamp()
+                .cmp(&r.timestamp())
+                .then_with(|| l.id.cmp(&r.id))
+        });
+        Box::new(candidates.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use chrono_tz::Etc::UTC;
+    use std::ops::Bound;
+
+    use super::Index;
+    use date_time_tz::DateTimeTz;
+    use interval::Interval;
+    use types::{Record, Recordable};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Reading {
+        time: DateTimeTz,
+        value: u32,
+    }
+
+    impl Recordable for Reading {
+        fn timestamp(&self) -> DateTimeTz {
+            self.time.clone()
+        }
+
+        fn tags(&self) -> Vec<String> {
+            let mut tags = vec![format!("tens-{}", self.value / 10)];
+            if self.value & 1 == 0 {
+                tags.push(String::from("even"));
+            }
+            tags
+        }
+    }
+
+    fn day(d: u32) -> DateTimeTz {
+        DateTimeTz(UTC.with_ymd_and_hms(2019, 5, d, 0, 0, 0).unwrap())
+    }
+
+    fn mk_index() -> Index<Reading> {
+        let mut index = Index::new();
+        for (d, value) in &[(4, 40), (1, 10), (3, 30), (2, 20), (3, 31)] {
+            index.insert(Record::new(Reading {
+                time: day(*d),
+                value: *value,
+            }));
+        }
+        index
+    }
+
+    fn values(
+        index: &Index<Reading>,
+        start: Bound<DateTimeTz>,
+        end: Bound<DateTimeTz>,
+    ) -> Vec<u32> {
+        let mut values: Vec<u32> = index
+            .range(Interval::from_bounds(start, end))
+            .map(|r| r.data.value)
+            .collect();
+        // Records at the same time come out in id order, which is random.
+        values.sort_by_key(|v| v / 10);
+        values
+    }
+
+    #[test]
+    fn iterates_in_time_order() {
+        let index = mk_index();
+        let times: Vec<DateTimeTz> = index.values().map(|r| r.timestamp()).collect();
+        assert_eq!(times, vec![day(1), day(2), day(3), day(3), day(4)]);
+    }
+
+    #[test]
+    fn ranges_respect_inclusivity() {
+        let index = mk_index();
+        assert_eq!(
+            values(&index, Bound::Included(day(2)), Bound::Included(day(3))).len(),
+            3
+        );
+        assert_eq!(
+            values(&index, Bound::Excluded(day(2)), Bound::Excluded(day(4))).len(),
+            2
+        );
+        assert_eq!(
+            values(&index, Bound::Unbounded, Bound::Excluded(day(3))),
+            vec![10, 20]
+        );
+    }
+
+    #[test]
+    fn empty_ranges_return_nothing() {
+        let index = mk_index();
+        assert!(values(&index, Bound::Included(day(3)), Bound::Included(day(2))).is_empty());
+        assert!(values(&index, Bound::Excluded(day(3)), Bound::Excluded(day(3))).is_empty());
+        assert!(values(&index, Bound::Included(day(3)), Bound::Excluded(day(3))).is_empty());
+    }
+
+    #[test]
+    fn removal_and_replacement_update_the_time_index() {
+        let mut index = mk_index();
+        let first = index.values().next().unwrap().clone();
+        let mut moved = first.clone();
+        moved.data.time = day(5);
+        index.insert(moved);
+        assert_eq!(index.len(), 5);
+        assert_eq!(
+            values(&index, Bound::Unbounded, Bound::Excluded(day(2))),
+            Vec::<u32>::new()
+        );
+
+        index.remove(&first.id);
+        assert_eq!(index.len(), 4);
+        assert_eq!(
+            values(&index, Bound::Included(day(5)), Bound::Unbounded),
+            Vec::<u32>::new()
+        );
+    }
+
+    #[test]
+    fn searches_by_tag_within_bounds() {
+        let index = mk_index();
+        let tags = |ts: &[&str]| ts.iter().map(|t| String::from(*t)).collect::<Vec<String>>();
+        let unbounded = || Interval::unbounded();
+
+        let evens: Vec<u32> = index
+            .search(unbounded(), &tags(&["even"]))
+            .map(|r| r.data.value)
+            .collect();
+        assert_eq!(evens, vec![10, 20, 30, 40]);
+
+        let threes: Vec<u32> = index
+            .search(unbounded(), &tags(&["tens-3"]))
+            .map(|r| r.data.value)
+            .collect();
+        assert_eq!(threes.len(), 2);
+
+        let even_threes: Vec<u32> = index
+            .search(unbounded(), &tags(&["tens-3", "even"]))
+            .map(|r| r.data.value)
+            .collect();
+        assert_eq!(even_threes, vec![30]);
+
+        let early_evens: Vec<u32> = index
+            .search(
+                Interval::from_bounds(Bound::Excluded(day(1)), Bound::Included(day(3))),
+                &tags(&["even"]),
+            )
+            .map(|r| r.data.value)
+            .collect();
+        assert_eq!(early_evens, vec![20, 30]);
+
+        assert_eq!(index.search(unbounded(), &tags(&["missing"])).count(), 0);
+    }
+
+    #[test]
+    fn removal_updates_the_tag_index() {
+        let mut index = mk_index();
+        let ten = index.values().next().unwrap().clone();
+        index.remove(&ten.id);
+        assert_eq!(
+            index
+                .search(Interval::unbounded(), &[String::from("tens-1")])
+                .count(),
+            0
+        );
+        assert!(!index.by_tag.contains_key("tens-1"));
+    }
+}
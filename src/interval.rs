@@ -1,12 +1,17 @@
 // NOTE: this module is a candidate for extraction into its own crate, or should be replaced with
 // an existing crate.
 
-/// Specify an interval across the data type T
+extern crate chrono;
+
+use date_time_tz::DateTimeTz;
+use std::ops::Bound;
+
+/// Specify an interval across the data type T. Either end may be inclusive, exclusive, or
+/// unbounded.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Interval<T> {
-    start: T,
-    start_incl: bool,
-    end: T,
-    end_incl: bool,
+    pub start: Bound<T>,
+    pub end: Bound<T>,
 }
 
 impl<T> Interval<T>
@@ -17,30 +22,301 @@ where
     /// either end of the interval.
     pub fn new(start: T, start_incl: bool, end: T, end_incl: bool) -> Interval<T> {
         Interval {
-            start,
-            start_incl,
-            end,
-            end_incl,
+            start: if start_incl {
+                Bound::Included(start)
+            } else {
+                Bound::Excluded(start)
+            },
+            end: if end_incl {
+                Bound::Included(end)
+            } else {
+                Bound::Excluded(end)
+            },
         }
     }
 
+    /// Create an interval from a pair of bounds, any of which may be `Bound::Unbounded`.
+    pub fn from_bounds(start: Bound<T>, end: Bound<T>) -> Interval<T> {
+        Interval { start, end }
+    }
+
     /// Create an interval that matches *exactly* the specified value.
     pub fn exact(val: T) -> Interval<T> {
         Interval {
-            start: val.clone(),
-            start_incl: true,
-            end: val,
-            end_incl: true,
+            start: Bound::Included(val.clone()),
+            end: Bound::Included(val),
+        }
+    }
+
+    /// Create an interval that includes every value.
+    pub fn unbounded() -> Interval<T> {
+        Interval {
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+        }
+    }
+
+    /// Create an interval that includes every value after the start, with no end.
+    pub fn starting(start: T, start_incl: bool) -> Interval<T> {
+        Interval {
+            start: if start_incl {
+                Bound::Included(start)
+            } else {
+                Bound::Excluded(start)
+            },
+            end: Bound::Unbounded,
+        }
+    }
+
+    /// Create an interval that includes every value before the end, with no start.
+    pub fn ending(end: T, end_incl: bool) -> Interval<T> {
+        Interval {
+            start: Bound::Unbounded,
+            end: if end_incl {
+                Bound::Included(end)
+            } else {
+                Bound::Excluded(end)
+            },
         }
     }
 
     /// Test whether a value is included in the specified interval.
-    pub fn contains(&self, val: T) -> bool {
-        match (self.start_incl, self.end_incl) {
-            (true, true) => self.start <= val && val <= self.end,
-            (true, false) => self.start <= val && val < self.end,
-            (false, true) => self.start < val && val <= self.end,
-            (false, false) => self.start < val && val < self.end,
+    pub fn contains(&self, val: &T) -> bool {
+        let after_start = match self.start {
+            Bound::Included(ref start) => val >= start,
+            Bound::Excluded(ref start) => val > start,
+            Bound::Unbounded => true,
+        };
+        let before_end = match self.end {
+            Bound::Included(ref end) => val <= end,
+            Bound::Excluded(ref end) => val < end,
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    /// Test whether the interval excludes every possible value, as happens when the start comes
+    /// after the end.
+    pub fn is_empty(&self) -> bool {
+        match (&self.start, &self.end) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (Bound::Included(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+            _ => false,
+        }
+    }
+
+    /// The interval of values that are in both this interval and the other. This is empty if the
+    /// two do not overlap.
+    pub fn intersection(&self, other: &Interval<T>) -> Interval<T> {
+        Interval {
+            start: if starts_later(&self.start, &other.start) {
+                self.start.clone()
+            } else {
+                other.start.clone()
+            },
+            end: if ends_earlier(&self.end, &other.end) {
+                self.end.clone()
+            } else {
+                other.end.clone()
+            },
+        }
+    }
+
+    /// The smallest interval that includes every value in this interval and in the other. This
+    /// also includes any gap between the two.
+    pub fn hull(&self, other: &Interval<T>) -> Interval<T> {
+        if self.is_empty() {
+            return other.clone();
+        }
+        if other.is_empty() {
+            return self.clone();
+        }
+        Interval {
+            start: if starts_later(&self.start, &other.start) {
+                other.start.clone()
+            } else {
+                self.start.clone()
+            },
+            end: if ends_earlier(&self.end, &other.end) {
+                other.end.clone()
+            } else {
+                self.end.clone()
+            },
+        }
+    }
+
+    /// The interval of values that are in either this interval or the other, or `None` if there
+    /// is a gap between the two, so that no single interval covers exactly those values.
+    pub fn union(&self, other: &Interval<T>) -> Option<Interval<T>> {
+        if self.is_empty() {
+            return Some(other.clone());
+        }
+        if other.is_empty() {
+            return Some(self.clone());
+        }
+        let (first, second) = if starts_later(&other.start, &self.start) {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let gap = Interval {
+            start: complement(&first.end),
+            end: complement(&second.start),
+        };
+        if gap.start == Bound::Unbounded || gap.end == Bound::Unbounded || gap.is_empty() {
+            Some(self.hull(other))
+        } else {
+            None
         }
     }
+
+    /// Test whether this interval and the other have any value in common.
+    pub fn overlaps(&self, other: &Interval<T>) -> bool {
+        !self.intersection(other).is_empty()
+    }
+}
+
+impl Interval<DateTimeTz> {
+    /// The length of time between the start and the end of the interval, or `None` if either end
+    /// is unbounded. An empty interval has a duration of zero.
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        if self.is_empty() {
+            return Some(chrono::Duration::zero());
+        }
+        match (&self.start, &self.end) {
+            (Bound::Unbounded, _) | (_, Bound::Unbounded) => None,
+            (Bound::Included(start), Bound::Included(end))
+            | (Bound::Included(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Excluded(start), Bound::Excluded(end)) => {
+                Some(end.0.signed_duration_since(start.0))
+            }
+        }
+    }
+}
+
+/// Whether lower bound `l` excludes at least everything that `r` does.
+fn starts_later<T: Ord>(l: &Bound<T>, r: &Bound<T>) -> bool {
+    match (l, r) {
+        (Bound::Unbounded, Bound::Unbounded) => true,
+        (Bound::Unbounded, _) => false,
+        (_, Bound::Unbounded) => true,
+        (Bound::Included(lt), Bound::Excluded(rt)) => lt > rt,
+        (Bound::Included(lt), Bound::Included(rt))
+        | (Bound::Excluded(lt), Bound::Included(rt))
+        | (Bound::Excluded(lt), Bound::Excluded(rt)) => lt >= rt,
+    }
+}
+
+/// Whether upper bound `l` excludes at least everything that `r` does.
+fn ends_earlier<T: Ord>(l: &Bound<T>, r: &Bound<T>) -> bool {
+    match (l, r) {
+        (Bound::Unbounded, Bound::Unbounded) => true,
+        (Bound::Unbounded, _) => false,
+        (_, Bound::Unbounded) => true,
+        (Bound::Included(lt), Bound::Excluded(rt)) => lt < rt,
+        (Bound::Included(lt), Bound::Included(rt))
+        | (Bound::Excluded(lt), Bound::Included(rt))
+        | (Bound::Excluded(lt), Bound::Excluded(rt)) => lt <= rt,
+    }
+}
+
+/// The bound that admits exactly the values on the other side of `bound`.
+fn complement<T: Clone>(bound: &Bound<T>) -> Bound<T> {
+    match bound {
+        Bound::Included(val) => Bound::Excluded(val.clone()),
+        Bound::Excluded(val) => Bound::Included(val.clone()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone};
+    use chrono_tz::Etc::UTC;
+    use std::ops::Bound;
+
+    use super::Interval;
+    use date_time_tz::DateTimeTz;
+
+    fn day(d: u32) -> DateTimeTz {
+        DateTimeTz(UTC.with_ymd_and_hms(2019, 5, d, 0, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn contains_respects_inclusivity_and_open_ends() {
+        let interval = Interval::new(2, true, 4, false);
+        assert!(!interval.contains(&1));
+        assert!(interval.contains(&2));
+        assert!(interval.contains(&3));
+        assert!(!interval.contains(&4));
+
+        assert!(Interval::exact(3).contains(&3));
+        assert!(!Interval::exact(3).contains(&4));
+        assert!(Interval::starting(3, false).contains(&1000));
+        assert!(!Interval::starting(3, false).contains(&3));
+        assert!(Interval::ending(3, true).contains(&-1000));
+        assert!(Interval::<i32>::unbounded().contains(&0));
+    }
+
+    #[test]
+    fn detects_empty_intervals() {
+        assert!(Interval::new(3, true, 2, true).is_empty());
+        assert!(Interval::new(3, true, 3, false).is_empty());
+        assert!(!Interval::exact(3).is_empty());
+        assert!(!Interval::ending(3, false).is_empty());
+    }
+
+    #[test]
+    fn intersects_and_overlaps() {
+        let l = Interval::new(1, true, 5, false);
+        let r = Interval::starting(3, false);
+        assert_eq!(l.intersection(&r), Interval::new(3, false, 5, false));
+        assert!(l.overlaps(&r));
+
+        let touching = Interval::starting(5, true);
+        assert!(l.intersection(&touching).is_empty());
+        assert!(!l.overlaps(&touching));
+        assert!(Interval::new(1, true, 5, true).overlaps(&touching));
+    }
+
+    #[test]
+    fn unions_only_without_a_gap() {
+        let l = Interval::new(1, true, 5, false);
+        assert_eq!(
+            l.union(&Interval::new(5, true, 8, true)),
+            Some(Interval::new(1, true, 8, true))
+        );
+        assert_eq!(
+            Interval::new(3, false, 8, true).union(&l),
+            Some(Interval::new(1, true, 8, true))
+        );
+        assert_eq!(
+            l.union(&Interval::ending(1, false)),
+            Some(Interval::from_bounds(Bound::Unbounded, Bound::Excluded(5)))
+        );
+        assert_eq!(l.union(&Interval::new(5, false, 8, true)), None);
+        assert_eq!(l.union(&Interval::ending(0, true)), None);
+        assert_eq!(l.union(&Interval::starting(6, true)), None);
+        assert_eq!(l.union(&Interval::new(9, true, 0, true)), Some(l.clone()));
+        assert_eq!(
+            l.hull(&Interval::starting(6, true)),
+            Interval::starting(1, true)
+        );
+    }
+
+    #[test]
+    fn measures_duration() {
+        assert_eq!(
+            Interval::new(day(1), true, day(3), false).duration(),
+            Some(Duration::days(2))
+        );
+        assert_eq!(
+            Interval::new(day(3), true, day(1), false).duration(),
+            Some(Duration::zero())
+        );
+        assert_eq!(Interval::starting(day(1), true).duration(), None);
+    }
 }
@@ -32,7 +32,8 @@ impl Recordable for BikeTrip {
 ```
 
 Recordable requires implementations for `timestamp` and `tags`, both of which can be used for
-searching for records, and both of which may be used for indexing in the future.
+searching for records, and both of which are indexed in memory so that searches on them need not
+scan every record.
 
 The series can only store a single data type, but you can always store multiple data types by
 wrapping them into a single enum.
@@ -44,9 +45,24 @@ let mut ts: Series<BikeTrip> = Series::open("var/bike_trips.json")
     .expect("expect the time series to open correctly");
 ```
 
-The series file will be created if it does not already exist. If it does already exist, the existing data will be read into memory and made available.
+The series file will be created if it does not already exist. If it does already exist, the existing data will be read into memory and made available. A final line left half-written by a crash is cut off the end of the file; open through `SeriesOptions` with `Recovery::Strict` to refuse such files instead. `SeriesOptions` also controls file creation, locking, syncing, automatic compaction, and how long records are retained:
 
-Note: all of the data is read into memory at once. For human-scale things, this probably takes up very little memory, but this software is not optimized for IoT scale deployments. Additionally, this library assumes only one process is writing to the file. Behavior from more than one process writing to the file is currently undefined.
+```text
+let mut ts: Series<BikeTrip> = SeriesOptions::new()
+    .recovery(Recovery::Strict)
+    .sync_policy(SyncPolicy::Always)
+    .open("var/bike_trips.json")
+    .expect("expect the time series to open correctly");
+```
+
+To inspect a series without any chance of modifying it, open it read-only. This never creates the file, and the resulting `Series<BikeTrip, ReadOnly>` has no `put`, `insert`, `update`, `upsert`, or `delete`:
+
+```text
+let ts: Series<BikeTrip, ReadOnly> = Series::open_read_only("var/bike_trips.json")
+    .expect("expect the time series to open correctly");
+```
+
+Note: all of the data is read into memory at once. For human-scale things, this probably takes up very little memory, but this software is not optimized for IoT scale deployments. Additionally, only one process may have a series open for writing at a time. An open series holds an exclusive advisory lock on its file, and opening it from a second process fails with `Error::FileLocked`. Advisory locks only protect against other programs that also take them. To share a series between the threads of one process, wrap it in a `SharedSeries`, which lets one thread at a time write to it while any number of threads read a snapshot of the records as of the last finished write. Readers never wait for a write or its file I/O. A series opened with `LockMode::Unlocked` can follow the writes of another process with `refresh`, or with `SharedSeries::watch`, which refreshes it periodically.
 */
 
 #[macro_use]
@@ -55,12 +71,36 @@ extern crate chrono;
 extern crate chrono_tz;
 extern crate serde;
 
+mod aggregate;
+#[cfg(feature = "async")]
+mod async_series;
+mod batch;
 mod criteria;
 mod date_time_tz;
+mod history;
+mod index;
+mod interval;
+mod options;
+mod query;
+mod rollup;
 mod series;
+mod shared;
+mod subscription;
+mod syncer;
 mod types;
 
-pub use date_time_tz::DateTimeTz;
 pub use criteria::*;
-pub use series::Series;
+pub use aggregate::{Aggregate, Bucket, BucketZone, Reducer};
+#[cfg(feature = "async")]
+pub use async_series::{AsyncSeries, Pending};
+pub use batch::Batch;
+pub use date_time_tz::DateTimeTz;
+pub use history::{AsOf, Snapshot, Version};
+pub use interval::Interval;
+pub use options::{LockMode, RetentionPolicy, SeriesOptions};
+pub use query::Query;
+pub use rollup::RollupPoint;
+pub use series::{Access, ReadOnly, ReadWrite, Recovery, Series, SyncPolicy, TornLine};
+pub use shared::{SharedSeries, Watcher, WriteGuard};
+pub use subscription::{Event, SubscriptionId};
 pub use types::{Error, Record, Recordable, UniqueId};
@@ -0,0 +1,164 @@
+extern crate chrono;
+extern crate serde;
+
+use self::serde::de::DeserializeOwned;
+use self::serde::ser::Serialize;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use series::{Access, ReadOnly, ReadWrite, Recovery, Series, SyncPolicy};
+use types::{Error, Recordable};
+
+/// The advisory lock that an open series holds on its file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockMode {
+    /// No other process may hold any lock on the file. This is the default for a series opened
+    /// for writing.
+    Exclusive,
+
+    /// Other processes may hold shared locks, but not an exclusive one. This is the default for a
+    /// series opened read-only.
+    Shared,
+
+    /// Take no lock at all, and ignore any locks that other processes hold.
+    Unlocked,
+}
+
+/// Which records a series keeps. Records that fall outside the policy are removed from the series
+/// when it is opened and whenever it is compacted, and the compaction that follows removes them
+/// from the file entirely. The default keeps everything.
+///
+/// Expiring records does not touch the rollups of the series, which go on summarizing them. A
+/// bucket is only recomputed from the remaining records if a later write lands in it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RetentionPolicy {
+    /// Drop records whose timestamps are further in the past than this.
+    pub max_age: Option<chrono::Duration>,
+
+    /// Keep at most this many records, dropping the oldest by timestamp.
+    pub max_records: Option<usize>,
+}
+
+/// Options and flags which can be used to configure how a series is opened, in the style of
+/// `std::fs::OpenOptions`.
+///
+/// ```text
+/// let ts: Series<BikeTrip> = SeriesOptions::new()
+///     .sync_policy(SyncPolicy::Always)
+///     .compaction_threshold(Some(1000))
+///     .open("var/bike_trips.json")
+///     .expect("expect the time series to open correctly");
+/// ```
+///
+/// Calling `read_only` switches to building a `Series<T, ReadOnly>`; options that only make sense
+/// for writing are not available after that.
+#[derive(Clone, Debug)]
+pub struct SeriesOptions<A: Access = ReadWrite> {
+    pub(crate) create: bool,
+    pub(crate) recovery: Recovery,
+    pub(crate) sync_policy: SyncPolicy,
+    pub(crate) compaction_threshold: Option<usize>,
+    pub(crate) lock_mode: Option<LockMode>,
+    pub(crate) retention: RetentionPolicy,
+    access: PhantomData<A>,
+}
+
+impl SeriesOptions<ReadWrite> {
+    /// Create a set of options for opening a series for reading and writing. The defaults match
+    /// `Series::open`.
+    pub fn new() -> SeriesOptions<ReadWrite> {
+        SeriesOptions {
+            create: true,
+            recovery: Recovery::Truncate,
+            sync_policy: SyncPolicy::Never,
+            compaction_threshold: None,
+            lock_mode: None,
+            retention: RetentionPolicy::default(),
+            access: PhantomData,
+        }
+    }
+
+    /// Open the series read-only instead. The file is never created or modified.
+    pub fn read_only(self) -> SeriesOptions<ReadOnly> {
+        SeriesOptions {
+            create: false,
+            recovery: self.recovery,
+            sync_policy: SyncPolicy::Never,
+            compaction_threshold: None,
+            lock_mode: self.lock_mode,
+            retention: RetentionPolicy::default(),
+            access: PhantomData,
+        }
+    }
+
+    /// Whether to create the file if it does not exist. Defaults to true.
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    /// When writes get synced to stable storage. Defaults to `SyncPolicy::Never`.
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// The number of superseded lines the file may accumulate before it is compacted
+    /// automatically. Defaults to `None`, which disables automatic compaction.
+    pub fn compaction_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.compaction_threshold = threshold;
+        self
+    }
+
+    /// Which records to keep. Defaults to keeping everything.
+    pub fn retention(mut self, policy: RetentionPolicy) -> Self {
+        self.retention = policy;
+        self
+    }
+
+    /// Open the series at `path` with these options. If the file holds records outside the
+    /// retention policy, or is already past the compaction threshold, it is compacted before this
+    /// returns.
+    pub fn open<T, P>(&self, path: P) -> Result<Series<T, ReadWrite>, Error>
+    where
+        T: Clone + Recordable + DeserializeOwned + Serialize,
+        P: AsRef<Path>,
+    {
+        let mut series = Series::open_with_options(path.as_ref(), self)?;
+        series.set_retention_policy(self.retention)?;
+        series.set_compaction_threshold(self.compaction_threshold)?;
+        Ok(series)
+    }
+}
+
+impl Default for SeriesOptions<ReadWrite> {
+    fn default() -> SeriesOptions<ReadWrite> {
+        SeriesOptions::new()
+    }
+}
+
+impl SeriesOptions<ReadOnly> {
+    /// Open the series at `path` read-only with these options.
+    pub fn open<T, P>(&self, path: P) -> Result<Series<T, ReadOnly>, Error>
+    where
+        T: Clone + Recordable + DeserializeOwned + Serialize,
+        P: AsRef<Path>,
+    {
+        Series::open_with_options(path.as_ref(), self)
+    }
+}
+
+impl<A: Access> SeriesOptions<A> {
+    /// How to treat a partially written final line. Defaults to `Recovery::Truncate`.
+    pub fn recovery(mut self, recovery: Recovery) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// The advisory lock to hold on the file. Defaults to `LockMode::Exclusive` for writing and
+    /// `LockMode::Shared` for reading only.
+    pub fn lock_mode(mut self, mode: LockMode) -> Self {
+        self.lock_mode = Some(mode);
+        self
+    }
+}
@@ -0,0 +1,502 @@
+extern crate chrono;
+extern crate chrono_tz;
+
+use std::cmp::Ordering;
+use std::str;
+
+use criteria::{Comparison, Criteria, PayloadCriteria};
+use date_time_tz::DateTimeTz;
+use interval::Interval;
+use types::{Error, Recordable};
+
+/// A search written as text, such as one taken from a command line or a configuration file.
+///
+/// ```text
+/// let query: Query = "time >= 2019-05-01T00:00:00Z US/Central and tags has \"commute\" and not tags has \"race\""
+///     .parse()
+///     .expect("the query should parse");
+/// let trips = ts.search(query);
+/// ```
+///
+/// A query is made of these conditions:
+///
+/// * `time <op> <timestamp> [<time zone>]`, where `<op>` is one of `=`, `!=`, `<`, `<=`, `>` or
+///   `>=`, the timestamp is RFC 3339, and the optional time zone is a name from the tz database
+///   such as `US/Central`.
+/// * `tags has "<tag>"`, where the tag is a double-quoted string in which `\"` and `\\` stand for
+///   a quote and a backslash.
+///
+/// Conditions combine with `not`, `and` and `or`, in decreasing order of precedence, and may be
+/// grouped with parentheses. Parse errors are `Error::QueryParseError`, carrying the byte offset in
+/// the query at which the problem was found.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    Time(Comparison, DateTimeTz),
+    HasTag(String),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+impl Criteria for Query {
+    fn apply<T: Recordable>(&self, record: &T) -> bool {
+        match self {
+            Query::Time(comparison, time) => {
+                let ordering = record.timestamp().cmp(time);
+                match comparison {
+                    Comparison::Equal => ordering == Ordering::Equal,
+                    Comparison::NotEqual => ordering != Ordering::Equal,
+                    Comparison::LessThan => ordering == Ordering::Less,
+                    Comparison::LessOrEqual => ordering != Ordering::Greater,
+                    Comparison::GreaterThan => ordering == Ordering::Greater,
+                    Comparison::GreaterOrEqual => ordering != Ordering::Less,
+                }
+            }
+            Query::HasTag(tag) => record.tags().contains(tag),
+            Query::And(lside, rside) => lside.apply(record) && rside.apply(record),
+            Query::Or(lside, rside) => lside.apply(record) || rside.apply(record),
+            Query::Not(query) => !query.apply(record),
+        }
+    }
+
+    fn time_bounds(&self) -> Interval<DateTimeTz> {
+        match self {
+            Query::Time(comparison, time) => match comparison {
+                Comparison::Equal => Interval::exact(time.clone()),
+                Comparison::NotEqual => Interval::unbounded(),
+                Comparison::LessThan => Interval::ending(time.clone(), false),
+                Comparison::LessOrEqual => Interval::ending(time.clone(), true),
+                Comparison::GreaterThan => Interval::starting(time.clone(), false),
+                Comparison::GreaterOrEqual => Interval::starting(time.clone(), true),
+            },
+            Query::HasTag(_) => Interval::unbounded(),
+            Query::And(lside, rside) => lside.time_bounds().intersection(&rside.time_bounds()),
+            Query::Or(lside, rside) => lside.time_bounds().hull(&rside.time_bounds()),
+            Query::Not(_) => Interval::unbounded(),
+        }
+    }
+
+    fn required_tags(&self) -> Vec<String> {
+        match self {
+            Query::Time(_, _) => Vec::new(),
+            Query::HasTag(tag) => vec![tag.clone()],
+            Query::And(lside, rside) => {
+                let mut tags = lside.required_tags();
+                for tag in rside.required_tags() {
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+                tags
+            }
+            Query::Or(lside, rside) => {
+                let rtags = rside.required_tags();
+                lside
+                    .required_tags()
+                    .into_iter()
+                    .filter(|tag| rtags.contains(tag))
+                    .collect()
+            }
+            Query::Not(_) => Vec::new(),
+        }
+    }
+}
+
+impl<D: Recordable> PayloadCriteria<D> for Query {
+    fn apply_payload(&self, data: &D) -> bool {
+        self.apply(data)
+    }
+}
+
+impl str::FromStr for Query {
+    type Err = Error;
+
+    /// Parse a query. Raise QueryParseError if the query is malformed.
+    fn from_str(query: &str) -> Result<Query, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            next: 0,
+        };
+        let result = parser.parse_or()?;
+        match parser.advance() {
+            (_, Token::End) => Ok(result),
+            (position, token) => Err(parse_error(
+                position,
+                format!(
+                    "expected `and`, `or` or the end of the query, found {}",
+                    token.describe()
+                ),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(Comparison),
+    LParen,
+    RParen,
+    End,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("`{}`", word),
+            Token::Str(_) => String::from("a string"),
+            Token::Op(_) => String::from("a comparison"),
+            Token::LParen => String::from("`(`"),
+            Token::RParen => String::from("`)`"),
+            Token::End => String::from("the end of the query"),
+        }
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        match self {
+            Token::Word(w) => w == word,
+            _ => false,
+        }
+    }
+}
+
+fn parse_error(position: usize, message: String) -> Error {
+    Error::QueryParseError { position, message }
+}
+
+/// Split a query into tokens, each paired with its byte offset in the query.
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            tokens.push((start, Token::LParen));
+        } else if c == ')' {
+            chars.next();
+            tokens.push((start, Token::RParen));
+        } else if c == '"' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) if c == '"' || c == '\\' => string.push(c),
+                        Some((position, _)) => {
+                            return Err(parse_error(
+                                position - 1,
+                                String::from("only `\\\"` and `\\\\` may be escaped"),
+                            ))
+                        }
+                        None => {
+                            return Err(parse_error(start, String::from("unterminated string")));
+                        }
+                    },
+                    Some((_, c)) => string.push(c),
+                    None => {
+                        return Err(parse_error(start, String::from("unterminated string")));
+                    }
+                }
+            }
+            tokens.push((start, Token::Str(string)));
+        } else if is_operator(c) {
+            let mut op = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !is_operator(c) {
+                    break;
+                }
+                op.push(c);
+                chars.next();
+            }
+            let comparison = match op.as_str() {
+                "=" | "==" => Comparison::Equal,
+                "!=" => Comparison::NotEqual,
+                "<" => Comparison::LessThan,
+                "<=" => Comparison::LessOrEqual,
+                ">" => Comparison::GreaterThan,
+                ">=" => Comparison::GreaterOrEqual,
+                _ => return Err(parse_error(start, format!("`{}` is not a comparison", op))),
+            };
+            tokens.push((start, Token::Op(comparison)));
+        } else {
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == '(' || c == ')' || c == '"' || is_operator(c) {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push((start, Token::Word(word)));
+        }
+    }
+    tokens.push((query.len(), Token::End));
+    Ok(tokens)
+}
+
+fn is_operator(c: char) -> bool {
+    c == '<' || c == '>' || c == '=' || c == '!'
+}
+
+/// A recursive descent parser over the tokens of a query.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].1
+    }
+
+    fn advance(&mut self) -> (usize, Token) {
+        let token = self.tokens[self.next].clone();
+        if token.1 != Token::End {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Query, Error> {
+        let mut query = self.parse_and()?;
+        while self.peek().is_word("or") {
+            self.advance();
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, Error> {
+        let mut query = self.parse_unary()?;
+        while self.peek().is_word("and") {
+            self.advance();
+            query = Query::And(Box::new(query), Box::new(self.parse_unary()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, Error> {
+        match self.advance() {
+            (_, Token::LParen) => {
+                let query = self.parse_or()?;
+                match self.advance() {
+                    (_, Token::RParen) => Ok(query),
+                    (position, token) => Err(parse_error(
+                        position,
+                        format!("expected `)`, found {}", token.describe()),
+                    )),
+                }
+            }
+            (_, ref token) if token.is_word("not") => Ok(Query::Not(Box::new(self.parse_unary()?))),
+            (_, ref token) if token.is_word("time") => self.parse_time(),
+            (_, ref token) if token.is_word("tags") => self.parse_tags(),
+            (position, token) => Err(parse_error(
+                position,
+                format!(
+                    "expected `time`, `tags`, `not` or `(`, found {}",
+                    token.describe()
+                ),
+            )),
+        }
+    }
+
+    fn parse_time(&mut self) -> Result<Query, Error> {
+        let comparison = match self.advance() {
+            (_, Token::Op(comparison)) => comparison,
+            (position, token) => {
+                return Err(parse_error(
+                    position,
+                    format!("expected a comparison, found {}", token.describe()),
+                ))
+            }
+        };
+        let (position, timestamp) = match self.advance() {
+            (position, Token::Word(word)) => (position, word),
+            (position, token) => {
+                return Err(parse_error(
+                    position,
+                    format!("expected a timestamp, found {}", token.describe()),
+                ))
+            }
+        };
+        let timestamp = chrono::DateTime::parse_from_rfc3339(&timestamp).map_err(|err| {
+            parse_error(
+                position,
+                format!("`{}` is not an RFC 3339 timestamp: {}", timestamp, err),
+            )
+        })?;
+
+        let zone = match self.peek() {
+            Token::Word(word) if word != "and" && word != "or" => Some(word.clone()),
+            _ => None,
+        };
+        let zone = match zone {
+            Some(zone) => {
+                let (position, _) = self.advance();
+                zone.parse::<chrono_tz::Tz>().map_err(|_| {
+                    parse_error(position, format!("`{}` is not a known time zone", zone))
+                })?
+            }
+            None => chrono_tz::Etc::UTC,
+        };
+        Ok(Query::Time(
+            comparison,
+            DateTimeTz(timestamp.with_timezone(&zone)),
+        ))
+    }
+
+    fn parse_tags(&mut self) -> Result<Query, Error> {
+        match self.advance() {
+            (_, ref token) if token.is_word("has") => (),
+            (position, token) => {
+                return Err(parse_error(
+                    position,
+                    format!("expected `has`, found {}", token.describe()),
+                ))
+            }
+        }
+        match self.advance() {
+            (_, Token::Str(tag)) => Ok(Query::HasTag(tag)),
+            (position, token) => Err(parse_error(
+                position,
+                format!("expected a quoted tag, found {}", token.describe()),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use chrono_tz::Etc::UTC;
+    use chrono_tz::US::Central;
+
+    use super::Query;
+    use criteria::{Comparison, Criteria};
+    use date_time_tz::DateTimeTz;
+    use interval::Interval;
+    use types::{Error, Recordable};
+
+    struct Entry {
+        time: DateTimeTz,
+        tags: Vec<String>,
+    }
+
+    impl Recordable for Entry {
+        fn timestamp(&self) -> DateTimeTz {
+            self.time.clone()
+        }
+
+        fn tags(&self) -> Vec<String> {
+            self.tags.clone()
+        }
+    }
+
+    fn day(d: u32) -> DateTimeTz {
+        DateTimeTz(UTC.with_ymd_and_hms(2019, 5, d, 0, 0, 0).unwrap())
+    }
+
+    fn entry(d: u32, tags: &[&str]) -> Entry {
+        Entry {
+            time: day(d),
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+        }
+    }
+
+    fn parse(query: &str) -> Query {
+        match query.parse() {
+            Ok(query) => query,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn error_position(query: &str) -> usize {
+        match query.parse::<Query>() {
+            Err(Error::QueryParseError { position, .. }) => position,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(query) => panic!("unexpectedly parsed {:?}", query),
+        }
+    }
+
+    #[test]
+    fn parses_the_example_query() {
+        let query = parse(
+            "time >= 2019-05-01T00:00:00Z US/Central and tags has \"commute\" and not tags has \"race\"",
+        );
+        assert_eq!(
+            query,
+            Query::And(
+                Box::new(Query::And(
+                    Box::new(Query::Time(
+                        Comparison::GreaterOrEqual,
+                        DateTimeTz(Central.with_ymd_and_hms(2019, 4, 30, 19, 0, 0).unwrap()),
+                    )),
+                    Box::new(Query::HasTag(String::from("commute"))),
+                )),
+                Box::new(Query::Not(Box::new(Query::HasTag(String::from("race"))))),
+            )
+        );
+        assert!(query.apply(&entry(1, &["commute"])));
+        assert!(!query.apply(&entry(1, &["commute", "race"])));
+        assert_eq!(query.time_bounds(), Interval::starting(day(1), true));
+        assert_eq!(query.required_tags(), vec![String::from("commute")]);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let query = parse("tags has \"a\" or tags has \"b\" and tags has \"c\"");
+        assert!(query.apply(&entry(1, &["a"])));
+        assert!(!query.apply(&entry(1, &["b"])));
+        assert!(query.apply(&entry(1, &["b", "c"])));
+
+        let query = parse("(tags has \"a\" or tags has \"b\") and tags has \"c\"");
+        assert!(!query.apply(&entry(1, &["a"])));
+        assert!(query.apply(&entry(1, &["a", "c"])));
+        assert_eq!(query.required_tags(), vec![String::from("c")]);
+    }
+
+    #[test]
+    fn compares_times() {
+        let query = parse("time > 2019-05-02T00:00:00Z and time<=2019-05-04T00:00:00+00:00");
+        assert!(!query.apply(&entry(2, &[])));
+        assert!(query.apply(&entry(3, &[])));
+        assert!(query.apply(&entry(4, &[])));
+        assert_eq!(
+            query.time_bounds(),
+            Interval::new(day(2), false, day(4), true)
+        );
+
+        let query = parse("time != 2019-05-02T00:00:00Z");
+        assert!(query.apply(&entry(1, &[])));
+        assert!(!query.apply(&entry(2, &[])));
+        assert_eq!(query.time_bounds(), Interval::unbounded());
+    }
+
+    #[test]
+    fn unescapes_tags() {
+        assert_eq!(
+            parse(r#"tags has "say \"hi\" \\ bye""#),
+            Query::HasTag(String::from(r#"say "hi" \ bye"#))
+        );
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(error_position("tags has commute"), 9);
+        assert_eq!(error_position("time >= yesterday"), 8);
+        assert_eq!(
+            error_position("time >= 2019-05-01T00:00:00Z Mars/Olympus"),
+            29
+        );
+        assert_eq!(error_position("time => 2019-05-01T00:00:00Z"), 5);
+        assert_eq!(error_position("tags has \"commute"), 9);
+        assert_eq!(error_position("(tags has \"a\" or tags has \"b\""), 29);
+        assert_eq!(error_position("tags has \"a\" tags has \"b\""), 13);
+        assert_eq!(error_position("not"), 3);
+        assert_eq!(error_position(""), 0);
+    }
+}
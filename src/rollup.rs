@@ -0,0 +1,238 @@
+extern crate serde;
+
+use self::serde::de::DeserializeOwned;
+use self::serde::ser::Serialize;
+use std::collections::BTreeSet;
+
+use aggregate::{bucket_of, summarize, Accumulator, Bucket, BucketZone};
+use date_time_tz::DateTimeTz;
+use index::Index;
+use interval::Interval;
+use series::Series;
+use types::{Error, Record, Recordable};
+
+/// A summary of the values of the records within one bucket of a rollup.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RollupPoint {
+    /// The start of the bucket, inclusive. This is also the timestamp of the point.
+    pub start: DateTimeTz,
+
+    /// The end of the bucket, exclusive.
+    pub end: DateTimeTz,
+
+    pub count: usize,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+
+    /// The value of the earliest record in the bucket.
+    pub first: f64,
+
+    /// The value of the latest record in the bucket.
+    pub last: f64,
+}
+
+impl RollupPoint {
+    fn new(start: DateTimeTz, end: DateTimeTz, acc: &Accumulator) -> RollupPoint {
+        RollupPoint {
+            start,
+            end,
+            count: acc.count,
+            sum: acc.sum,
+            min: acc.min,
+            max: acc.max,
+            mean: acc.sum / acc.count as f64,
+            first: acc.first,
+            last: acc.last,
+        }
+    }
+}
+
+impl Recordable for RollupPoint {
+    fn timestamp(&self) -> DateTimeTz {
+        self.start.clone()
+    }
+
+    fn tags(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// A companion series that summarizes the records of another series into time buckets, and which
+/// that series keeps up to date as its records change.
+///
+/// Records whose extracted value is not finite are left out: JSON has no way to write NaN or an
+/// infinity, so a point holding one could never be read back.
+pub(crate) struct Rollup<T> {
+    pub(crate) name: String,
+    bucket: Bucket,
+    zone: BucketZone,
+    extract: Box<dyn Fn(&T) -> f64 + Send + Sync>,
+    pub(crate) series: Series<RollupPoint>,
+
+    /// Whether the last attempt to update the points failed, leaving them out of date.
+    stale: bool,
+}
+
+impl<T> Rollup<T>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+{
+    pub(crate) fn new(
+        name: &str,
+        bucket: Bucket,
+        zone: BucketZone,
+        extract: Box<dyn Fn(&T) -> f64 + Send + Sync>,
+        series: Series<RollupPoint>,
+    ) -> Rollup<T> {
+        Rollup {
+            name: String::from(name),
+            bucket,
+            zone,
+            extract,
+            series,
+            stale: false,
+        }
+    }
+
+    /// Bring every point up to date with the records, writing only the points that changed.
+    ///
+    /// `cutoff` is the time before which a retention policy may have expired records. Points for
+    /// buckets that end by then and have no records left are left alone, since they summarize
+    /// records that are gone only from the series. Without a cutoff, such points are deleted.
+    pub(crate) fn rebuild(
+        &mut self,
+        records: &Index<T>,
+        cutoff: Option<&DateTimeTz>,
+    ) -> Result<(), Error> {
+        let result = self.rebuild_points(records, cutoff);
+        self.stale = result.is_err();
+        result
+    }
+
+    /// Whether the points may be out of date, because the last attempt to update them failed.
+    pub(crate) fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    fn rebuild_points(
+        &mut self,
+        records: &Index<T>,
+        cutoff: Option<&DateTimeTz>,
+    ) -> Result<(), Error> {
+        let extract = &self.extract;
+        let included = records
+            .values()
+            .filter(|record| extract(&record.data).is_finite());
+        let mut expected = summarize(included, self.bucket, self.zone, |record| {
+            extract(&record.data)
+        })?;
+        for existing in self.series.all_records()? {
+            let expired = match cutoff {
+                Some(cutoff) => existing.data.end <= *cutoff,
+                None => false,
+            };
+            if expired && !expected.contains_key(&existing.data.start) {
+                continue;
+            }
+            match expected.remove(&existing.data.start) {
+                Some((end, acc)) => {
+                    let point = RollupPoint::new(existing.data.start.clone(), end, &acc);
+                    if point != existing.data {
+                        self.series.update(Record {
+                            data: point,
+                            ..existing
+                        })?;
+                    }
+                }
+                None => {
+                    self.series.delete(&existing.id)?;
+                }
+            }
+        }
+        for (start, (end, acc)) in expected {
+            self.series.put(RollupPoint::new(start, end, &acc))?;
+        }
+        Ok(())
+    }
+
+    /// Bring the points up to date after a record changed from `old` to `new`, either of which
+    /// may be missing for a record that was added or deleted. Only the buckets that contained
+    /// either version are recomputed, unless the points are stale, in which case they are all
+    /// rebuilt as with `rebuild`.
+    pub(crate) fn record_changed(
+        &mut self,
+        records: &Index<T>,
+        cutoff: Option<&DateTimeTz>,
+        old: Option<&T>,
+        new: Option<&T>,
+    ) -> Result<(), Error> {
+        if self.stale {
+            return self.rebuild(records, cutoff);
+        }
+        let result = self.refresh_buckets(records, old, new);
+        self.stale = result.is_err();
+        result
+    }
+
+    fn refresh_buckets(
+        &mut self,
+        records: &Index<T>,
+        old: Option<&T>,
+        new: Option<&T>,
+    ) -> Result<(), Error> {
+        let buckets: BTreeSet<(DateTimeTz, DateTimeTz)> = old
+            .into_iter()
+            .chain(new)
+            .map(|data| bucket_of(self.bucket, self.zone, &data.timestamp()))
+            .collect::<Result<_, Error>>()?;
+        for (start, end) in buckets {
+            self.refresh_bucket(records, start, end)?;
+        }
+        Ok(())
+    }
+
+    fn refresh_bucket(
+        &mut self,
+        records: &Index<T>,
+        start: DateTimeTz,
+        end: DateTimeTz,
+    ) -> Result<(), Error> {
+        let (bucket, zone, extract) = (self.bucket, self.zone, &self.extract);
+        // Records in other zones may fall within the same span of time but into other buckets.
+        let in_bucket = records
+            .range(Interval::new(start.clone(), true, end, false))
+            .filter(
+                |record| match bucket_of(bucket, zone, &record.timestamp()) {
+                    Ok((record_start, _)) => record_start == start,
+                    Err(_) => false,
+                },
+            )
+            .filter(|record| extract(&record.data).is_finite());
+        let point = summarize(in_bucket, bucket, zone, |record| extract(&record.data))?
+            .into_iter()
+            .next()
+            .map(|(start, (end, acc))| RollupPoint::new(start, end, &acc));
+        let existing = self
+            .series
+            .search(Interval::exact(start))?
+            .into_iter()
+            .next();
+
+        match (point, existing) {
+            (Some(point), Some(existing)) => {
+                if point != existing.data {
+                    self.series.update(Record {
+                        data: point,
+                        ..existing
+                    })?;
+                }
+                Ok(())
+            }
+            (Some(point), None) => self.series.put(point).map(|_| ()),
+            (None, Some(existing)) => self.series.delete(&existing.id).map(|_| ()),
+            (None, None) => Ok(()),
+        }
+    }
+}
@@ -1,78 +1,303 @@
+extern crate chrono;
+extern crate chrono_tz;
+extern crate fs2;
 extern crate serde;
 extern crate serde_json;
 extern crate uuid;
 
+use self::chrono::{DateTime, Timelike, Utc};
+use self::fs2::FileExt;
 use self::serde::de::DeserializeOwned;
 use self::serde::ser::Serialize;
+use std::cmp;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, LineWriter, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aggregate;
+use aggregate::{Aggregate, Bucket, BucketZone, Reducer};
+use batch::Batch;
+use criteria::{Criteria, PayloadCriteria};
+use date_time_tz::DateTimeTz;
+use history::{AsOf, Snapshot, Version};
+use index::Index;
+use interval::Interval;
+use options::{LockMode, RetentionPolicy, SeriesOptions};
+use rollup::{Rollup, RollupPoint};
+use subscription::{Event, Subscribers, SubscriptionId};
+use syncer::Syncer;
+use types::{DeletableRecord, Error, LogLine, Record, Recordable, UniqueId};
+
+/// How to treat a final line that was only partially written, as happens when the process dies
+/// in the middle of a write.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Recovery {
+    /// Refuse to open the series, returning `JSONParseError` and leaving the file untouched.
+    Strict,
+
+    /// Cut the partial line off the end of the file and open the series with every record before
+    /// it. What was dropped is available from `Series::torn_line`. A read-only series skips the
+    /// partial line without modifying the file.
+    Truncate,
+}
+
+/// The remains of a partially written line that were cut from the end of a series file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TornLine {
+    /// The byte offset in the file at which the partial line started.
+    pub offset: u64,
+
+    /// The partial line itself. Invalid UTF-8 is replaced with U+FFFD.
+    pub contents: String,
+}
+
+/// When writes to the series file are forced out to stable storage with `fsync`.
+///
+/// Every write reaches the operating system as soon as its line is complete, so it survives the
+/// process crashing. Only a sync makes it survive a power loss or kernel crash.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// Sync after every write. This is the safest and the slowest choice.
+    Always,
+
+    /// Sync after every N writes.
+    EveryNWrites(usize),
+
+    /// Sync on a background thread, at most this long after each write. The thread runs for as
+    /// long as the series is open, and only syncs when something was written.
+    Interval(Duration),
+
+    /// Never sync automatically, leaving it to the operating system and to explicit calls to
+    /// `Series::sync`.
+    Never,
+}
+
+/// Everything recovered from reading a series file from start to finish.
+struct Loaded<T: Clone + Recordable> {
+    records: Index<T>,
+    log_records: usize,
+    revision: u64,
+
+    /// The offset just past the last complete line that was read.
+    offset: u64,
+
+    torn_line: Option<TornLine>,
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// How a series was opened, which determines the operations available on it. This is
+/// implemented only by `ReadWrite` and `ReadOnly`.
+pub trait Access: private::Sealed {
+    #[doc(hidden)]
+    type Handle;
+
+    #[doc(hidden)]
+    const WRITABLE: bool;
+
+    #[doc(hidden)]
+    const DEFAULT_LOCK: LockMode;
 
-use criteria::Criteria;
-use types::{DeletableRecord, Error, Record, Recordable, UniqueId};
+    #[doc(hidden)]
+    fn handle(f: File) -> Self::Handle;
+
+    #[doc(hidden)]
+    fn file(handle: &Self::Handle) -> &File;
+}
+
+/// A series that was opened for both reading and writing.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadWrite;
+
+/// A series that was opened for reading only. Its type has no methods which modify the file.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadOnly;
+
+impl private::Sealed for ReadWrite {}
+impl Access for ReadWrite {
+    type Handle = LineWriter<File>;
+    const WRITABLE: bool = true;
+    const DEFAULT_LOCK: LockMode = LockMode::Exclusive;
+
+    fn handle(f: File) -> LineWriter<File> {
+        LineWriter::new(f)
+    }
+
+    fn file(handle: &LineWriter<File>) -> &File {
+        handle.get_ref()
+    }
+}
+
+impl private::Sealed for ReadOnly {}
+impl Access for ReadOnly {
+    type Handle = File;
+    const WRITABLE: bool = false;
+    const DEFAULT_LOCK: LockMode = LockMode::Shared;
+
+    fn handle(f: File) -> File {
+        f
+    }
+
+    fn file(handle: &File) -> &File {
+        handle
+    }
+}
 
 /// An open time series database.
 ///
 /// Any given database can store only one data type, T. The data type must be determined when the
-/// database is opened.
-pub struct Series<T: Clone + Recordable + DeserializeOwned + Serialize> {
-    //path: String,
-    writer: LineWriter<File>,
-    records: HashMap<UniqueId, Record<T>>,
+/// database is opened. `A` records whether the database was opened read-only.
+pub struct Series<T: Clone + Recordable + DeserializeOwned + Serialize, A: Access = ReadWrite> {
+    path: PathBuf,
+    file: A::Handle,
+    lock_mode: LockMode,
+
+    /// The live records, shared with any snapshots taken of them. A write copies them only if a
+    /// snapshot still holds on to them.
+    records: Arc<Index<T>>,
+
+    /// The number of records currently in the file, including superseded versions and deletions.
+    /// A batch counts once for each record in it.
+    log_records: usize,
+
+    /// The highest revision of any line in the file.
+    revision: u64,
+
+    /// The offset in the file up to which every line has been applied to the records.
+    offset: u64,
+
+    /// The number of superseded lines after which the file gets compacted automatically.
+    compaction_threshold: Option<usize>,
+
+    retention: RetentionPolicy,
+
+    torn_line: Option<TornLine>,
+
+    sync_policy: SyncPolicy,
+    unsynced_writes: usize,
+
+    /// The thread that syncs the file for `SyncPolicy::Interval`.
+    syncer: Option<Syncer>,
+
+    rollups: Vec<Rollup<T>>,
+
+    subscribers: Subscribers<T>,
 }
 
-impl<T> Series<T>
+impl<T> Series<T, ReadWrite>
 where
     T: Clone + Recordable + DeserializeOwned + Serialize,
 {
     /// Open a time series database at the specified path. `path` is the full path and filename for
     /// the database.
-    pub fn open(path: &str) -> Result<Series<T>, Error> {
-        let f = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(&path)
-            .map_err(Error::IOError)?;
-
-        let records = Series::load_file(&f)?;
+    ///
+    /// The file is created if it does not exist, and a partially written final line is truncated
+    /// away, as with `Recovery::Truncate`. The series holds an exclusive advisory lock on the file
+    /// for as long as it is open. If another process already has the file open, this returns
+    /// `Error::FileLocked`. Use `SeriesOptions` to change any of this.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Series<T, ReadWrite>, Error> {
+        SeriesOptions::new().open(path)
+    }
 
-        let writer = LineWriter::new(f);
+    /// Serialize an entry holding `records` records and append it to the file as a single line.
+    /// Apply the entry to the records only once this succeeds, and then call `after_write`.
+    fn write_line<E: Serialize>(&mut self, entry: &E, records: usize) -> Result<(), Error> {
+        let rec_str = serde_json::to_string(entry).map_err(Error::JSONStringError)?;
+        self.file
+            .write_fmt(format_args!("{}\n", rec_str.as_str()))
+            .map_err(Error::IOError)?;
+        self.log_records += records;
+        self.offset += rec_str.len() as u64 + 1;
+        self.unsynced_writes += 1;
+        if let Some(ref syncer) = self.syncer {
+            syncer.wrote();
+        }
+        Ok(())
+    }
 
-        Ok(Series {
-            //path: String::from(path),
-            writer,
-            records,
-        })
+    /// Compact and sync the file as needed, once a line has been written and applied.
+    fn after_write(&mut self) -> Result<(), Error> {
+        // The line is in the file, so the write has succeeded whatever becomes of the compaction.
+        // A compaction that fails is tried again after the next write.
+        let _ = self.compact_if_needed();
+        self.sync_if_needed()
     }
 
-    /// Load a file and return all of the records in it.
-    fn load_file(f: &File) -> Result<HashMap<UniqueId, Record<T>>, Error> {
-        let mut records: HashMap<UniqueId, Record<T>> = HashMap::new();
-        let reader = BufReader::new(f);
-        for line in reader.lines() {
-            match line {
-                Ok(line_) => {
-                    match line_.parse::<DeletableRecord<_>>() {
-                        Ok(record) => match record.data {
-                            Some(val) => records.insert(
-                                record.id.clone(),
-                                Record {
-                                    id: record.id.clone(),
-                                    data: val,
-                                },
-                            ),
-                            None => records.remove(&record.id.clone()),
-                        },
-                        Err(err) => return Err(err),
-                    };
+    /// Set when writes are synced to stable storage. The default is `SyncPolicy::Never`.
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) -> Result<(), Error> {
+        self.sync_policy = policy;
+        self.syncer = match policy {
+            SyncPolicy::Interval(interval) => {
+                let f = self.file.get_ref().try_clone().map_err(Error::IOError)?;
+                let syncer = Syncer::start(f, interval);
+                if self.unsynced_writes > 0 {
+                    syncer.wrote();
                 }
-                Err(err) => return Err(Error::IOError(err)),
+                Some(syncer)
             }
+            _ => None,
+        };
+        for rollup in &mut self.rollups {
+            rollup.series.set_sync_policy(policy)?;
+        }
+        self.sync_if_needed()
+    }
+
+    /// Push any buffered writes out to the operating system.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        for rollup in &mut self.rollups {
+            rollup.series.flush()?;
+        }
+        self.file.flush().map_err(Error::IOError)
+    }
+
+    /// Flush any buffered writes and wait until the operating system has put them on stable
+    /// storage.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        for rollup in &mut self.rollups {
+            rollup.series.sync()?;
+        }
+        self.sync_file()
+    }
+
+    /// Sync the series file alone, leaving the rollups to sync their own.
+    fn sync_file(&mut self) -> Result<(), Error> {
+        self.file.flush().map_err(Error::IOError)?;
+        self.file.get_ref().sync_data().map_err(Error::IOError)?;
+        self.unsynced_writes = 0;
+        if let Some(ref syncer) = self.syncer {
+            syncer.synced();
+        }
+        Ok(())
+    }
+
+    fn sync_if_needed(&mut self) -> Result<(), Error> {
+        if self.unsynced_writes == 0 {
+            return Ok(());
+        }
+        let due = match self.sync_policy {
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryNWrites(n) => self.unsynced_writes >= n,
+            SyncPolicy::Interval(_) | SyncPolicy::Never => false,
+        };
+        // Each rollup follows the same policy for its own writes.
+        if due {
+            self.sync_file()
+        } else {
+            Ok(())
         }
-        Ok(records)
     }
 
     /// Put a new record into the database. A unique id will be assigned to the record and
@@ -80,501 +305,3262 @@ where
     pub fn put(&mut self, entry: T) -> Result<UniqueId, Error> {
         let record = Record::new(entry);
         let rec_id = record.id.clone();
-        self.update(record).and_then(|()| Ok(rec_id))
+        self.insert(record).map(|()| rec_id)
     }
 
-    /// Update an existing record. The `UniqueId` of the record passed into this function must match
-    /// the `UniqueId` of a record already in the database.
+    /// Add a new record. If a record with the same `UniqueId` is already in the database, nothing
+    /// is written and this returns `Error::AlreadyExists`.
+    pub fn insert(&mut self, record: Record<T>) -> Result<(), Error> {
+        if self.records.get(&record.id).is_some() {
+            return Err(Error::AlreadyExists(record.id));
+        }
+        self.write_record(record)
+    }
+
+    /// Update an existing record. If no record with the same `UniqueId` is in the database,
+    /// nothing is written and this returns `Error::NotFound`.
     pub fn update(&mut self, record: Record<T>) -> Result<(), Error> {
-        self.records.insert(record.id.clone(), record.clone());
-        let write_res = match serde_json::to_string(&record) {
-            Ok(rec_str) => self
-                .writer
-                .write_fmt(format_args!("{}\n", rec_str.as_str()))
-                .map_err(Error::IOError),
-            Err(err) => Err(Error::JSONStringError(err)),
-        };
+        if self.records.get(&record.id).is_none() {
+            return Err(Error::NotFound(record.id));
+        }
+        self.write_record(record)
+    }
 
-        match write_res {
-            Ok(_) => Ok(()),
-            Err(err) => Err(err),
+    /// Add a record, or update it if a record with the same `UniqueId` is already in the
+    /// database.
+    pub fn upsert(&mut self, record: Record<T>) -> Result<(), Error> {
+        self.write_record(record)
+    }
+
+    /// Update an existing record, but only if it is still at `expected_revision`, which is
+    /// usually the `revision` of the record as it was read. If anything else wrote or deleted the
+    /// record since, nothing is written and this returns `Error::RevisionConflict`.
+    pub fn compare_and_update(
+        &mut self,
+        record: Record<T>,
+        expected_revision: u64,
+    ) -> Result<(), Error> {
+        let actual = self
+            .records
+            .get(&record.id)
+            .map(|existing| existing.revision);
+        if actual != Some(expected_revision) {
+            return Err(Error::RevisionConflict {
+                expected: expected_revision,
+                actual,
+            });
         }
+        self.write_record(record)
+    }
+
+    /// Write a new version of a record, whether or not it already exists.
+    ///
+    /// The record is stamped with the time of the write and a new revision, replacing whatever
+    /// stamps it carried. Its creation time is kept from the version already in the database.
+    fn write_record(&mut self, mut record: Record<T>) -> Result<(), Error> {
+        let now = write_time();
+        record.created_at = match self.records.get(&record.id) {
+            Some(existing) => existing.created_at.clone(),
+            None => Some(now.clone()),
+        };
+        record.updated_at = Some(now);
+        record.revision = self.next_revision();
+        self.write_line(&record, 1)?;
+        let previous = Arc::make_mut(&mut self.records).insert(record.clone());
+        self.record_changed(previous, Some(record));
+        self.after_write()
     }
 
-    /// Delete a record from the database
+    /// Delete a record from the database, returning whether there was a record to delete. Nothing
+    /// is written if there was not.
     ///
-    /// Future note: while this deletes a record from the view, it only adds an entry to the
-    /// database that indicates `data: null`. If record histories ever become important, the record
-    /// and its entire history (including this delete) will still be available.
-    pub fn delete(&mut self, uuid: &UniqueId) -> Result<(), Error> {
-        self.records.remove(uuid);
+    /// This deletes the record from the view, but only adds an entry to the database that
+    /// indicates `data: null`. The record and its entire history (including this delete) remain
+    /// available through `history` until the next compaction.
+    pub fn delete(&mut self, uuid: &UniqueId) -> Result<bool, Error> {
+        if self.records.get(uuid).is_none() {
+            return Ok(false);
+        }
 
         let rec: DeletableRecord<T> = DeletableRecord {
             id: uuid.clone(),
             data: None,
+            created_at: None,
+            updated_at: Some(write_time()),
+            revision: self.next_revision(),
         };
-        match serde_json::to_string(&rec) {
-            Ok(rec_str) => self
-                .writer
-                .write_fmt(format_args!("{}\n", rec_str.as_str()))
-                .map_err(Error::IOError),
-            Err(err) => Err(Error::JSONStringError(err)),
-        }
+        self.write_line(&rec, 1)?;
+        let previous = Arc::make_mut(&mut self.records).remove(uuid);
+        self.record_changed(previous, None);
+        self.after_write()?;
+        Ok(true)
     }
 
-    /// Get all of the records in the database.
-    pub fn all_records(&self) -> Result<Vec<Record<T>>, Error> {
-        let results = self.records.iter().map(|tr| tr.1.clone()).collect();
-        Ok(results)
+    /// Start a batch of changes to this series, which are written together, atomically, when the
+    /// batch is committed. See `Batch`.
+    pub fn batch(&mut self) -> Batch<'_, T> {
+        Batch::new(self)
     }
 
-    pub fn records<'s>(&'s self) -> Result<impl Iterator<Item = &'s Record<T>> + 's, Error> {
-        Ok(self.records.values())
+    /// Whether a live record has the given id.
+    pub(crate) fn contains(&self, uuid: &UniqueId) -> bool {
+        self.records.get(uuid).is_some()
     }
 
-    /*  The point of having Search is so that a lot of internal optimizations can happen once the
-     *  data sets start getting large. */
-    /// Perform a search on the records in a database, based on the given criteria.
-    pub fn search<C>(&self, criteria: C) -> Result<Vec<Record<T>>, Error>
-    where
-        C: Criteria,
-    {
-        let results: Vec<Record<T>> = self
-            .records
-            .iter()
-            .filter(|&tr| criteria.apply(tr.1))
-            .map(|tr| tr.1.clone())
-            .collect();
-        Ok(results)
+    /// Stamp the records of a batch, write them to the file as a single line, and apply them.
+    pub(crate) fn write_batch(&mut self, mut batch: Vec<DeletableRecord<T>>) -> Result<(), Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let now = write_time();
+        // The creation time of each record that the batch has written so far, or `None` for one
+        // that it deleted.
+        let mut created: HashMap<UniqueId, Option<Option<DateTimeTz>>> = HashMap::new();
+        for record in &mut batch {
+            let existing = match created.get(&record.id) {
+                Some(existing) => existing.clone(),
+                None => self
+                    .records
+                    .get(&record.id)
+                    .map(|existing| existing.created_at.clone()),
+            };
+            if record.data.is_some() {
+                record.created_at = existing.unwrap_or_else(|| Some(now.clone()));
+                created.insert(record.id.clone(), Some(record.created_at.clone()));
+            } else {
+                created.insert(record.id.clone(), None);
+            }
+            record.updated_at = Some(now.clone());
+            record.revision = self.next_revision();
+        }
+
+        let count = batch.len();
+        let line = LogLine::Batch { batch };
+        self.write_line(&line, count)?;
+        let mut changes = Vec::with_capacity(count);
+        for record in line.into_records() {
+            let id = record.id.clone();
+            let previous = self.records.get(&id).cloned();
+            apply_record(Arc::make_mut(&mut self.records), record);
+            changes.push((previous, self.records.get(&id).cloned()));
+        }
+        for (old, new) in changes {
+            self.record_changed(old, new);
+        }
+        self.after_write()
     }
 
-    /// Perform a search and sort the resulting records based on the comparison.
-    pub fn search_sorted<C, CMP>(&self, criteria: C, compare: CMP) -> Result<Vec<Record<T>>, Error>
+    fn next_revision(&mut self) -> u64 {
+        self.revision += 1;
+        self.revision
+    }
+
+    /// Maintain a rollup of this series: a companion series at `path` which summarizes the
+    /// values that `extract` takes from the records into buckets. The rollup is brought up to
+    /// date with the records now, and kept up to date as they are put, updated, and deleted.
+    /// Adding a rollup with the same name as an existing one replaces it. Records for which
+    /// `extract` returns NaN or an infinity are left out of the rollup.
+    ///
+    /// A rollup follows the sync policy and compaction threshold of its series, and is compacted
+    /// along with it. Read it through `rollup`.
+    ///
+    /// ```text
+    /// ts.add_rollup(
+    ///     "5m",
+    ///     "var/temperature-5m.json",
+    ///     Bucket::Fixed(Duration::minutes(5)),
+    ///     BucketZone::Utc,
+    ///     |reading: &Reading| reading.celsius,
+    /// )?;
+    /// let means = ts.rollup("5m").unwrap().search(time_range(start, true, end, false))?;
+    /// ```
+    pub fn add_rollup<P, F>(
+        &mut self,
+        name: &str,
+        path: P,
+        bucket: Bucket,
+        zone: BucketZone,
+        extract: F,
+    ) -> Result<(), Error>
     where
-        C: Criteria,
-        CMP: FnMut(&Record<T>, &Record<T>) -> Ordering,
+        P: AsRef<Path>,
+        F: Fn(&T) -> f64 + Send + Sync + 'static,
     {
-        match self.search(criteria) {
-            Ok(mut records) => {
-                records.sort_by(compare);
-                Ok(records)
+        self.rollups.retain(|rollup| rollup.name != name);
+        let series = SeriesOptions::new()
+            .sync_policy(self.sync_policy)
+            .compaction_threshold(self.compaction_threshold)
+            .lock_mode(self.lock_mode)
+            .open(path)?;
+        let mut rollup = Rollup::new(name, bucket, zone, Box::new(extract), series);
+        rollup.rebuild(&self.records, self.retention_cutoff().as_ref())?;
+        self.rollups.push(rollup);
+        Ok(())
+    }
+
+    /// Stop maintaining the rollup with the given name, closing its file. The file itself is
+    /// left in place.
+    pub fn remove_rollup(&mut self, name: &str) {
+        self.rollups.retain(|rollup| rollup.name != name);
+    }
+
+    /// Rewrite the file so that it contains exactly one line per live record, dropping all
+    /// superseded versions and deletions. If the latest revision belonged to a record that is
+    /// dropped, the file starts with a line that keeps it, so that revisions are never reused.
+    ///
+    /// The new contents are written to a temporary file alongside the series, synced to disk, and
+    /// then renamed over the original. A crash at any point leaves either the old file or the
+    /// fully compacted one in place.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        self.expire(Utc::now());
+        let cutoff = self.retention_cutoff();
+        for rollup in &mut self.rollups {
+            if rollup.is_stale() {
+                rollup.rebuild(&self.records, cutoff.as_ref())?;
             }
-            Err(err) => Err(err),
+            rollup.series.compact()?;
+        }
+        self.file.flush().map_err(Error::IOError)?;
+
+        let tmp_path = compaction_path(&self.path);
+        if tmp_path.exists() {
+            // Left over from a compaction that was interrupted before the rename.
+            fs::remove_file(&tmp_path).map_err(Error::IOError)?;
+        }
+
+        let f = match self.write_compacted(&tmp_path) {
+            Ok(f) => f,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+        };
+        let len = f.metadata().map_err(Error::IOError)?.len();
+        let for_syncer = match self.syncer {
+            Some(_) => Some(f.try_clone().map_err(Error::IOError)?),
+            None => None,
+        };
+        fs::rename(&tmp_path, &self.path).map_err(Error::IOError)?;
+
+        // Switch over to the new file before anything else can fail, so that later writes never
+        // go to the old one.
+        self.offset = len;
+        self.file = LineWriter::new(f);
+        self.log_records = self.records.len();
+        self.unsynced_writes = 0;
+        if let (Some(syncer), Some(f)) = (self.syncer.as_ref(), for_syncer) {
+            syncer.synced();
+            syncer.replace_file(f);
         }
+        sync_parent_dir(&self.path)
     }
 
-    /// Get an exact record from the database based on unique id.
-    pub fn get(&self, uuid: &UniqueId) -> Result<Option<Record<T>>, Error> {
-        let val = self.records.get(uuid);
-        Ok(val.cloned())
+    /// Write every live record, in time order, to a brand new file at `tmp_path`.
+    fn write_compacted(&self, tmp_path: &Path) -> Result<File, Error> {
+        let f = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(tmp_path)
+            .map_err(Error::IOError)?;
+        // Lock before the rename, so that there is never a moment at which the file at `path` is
+        // unlocked.
+        lock(&f, self.lock_mode)?;
+
+        {
+            let mut writer = BufWriter::new(&f);
+            let live = self.records.values().map(|record| record.revision).max();
+            if live.unwrap_or(0) < self.revision {
+                // The record that carried the latest revision is gone, so keep the revision on a
+                // line of its own, for the revisions written after the compaction to follow on.
+                let mark: LogLine<T> = LogLine::Revision {
+                    last_revision: self.revision,
+                };
+                let mark_str = serde_json::to_string(&mark).map_err(Error::JSONStringError)?;
+                writer
+                    .write_fmt(format_args!("{}\n", mark_str.as_str()))
+                    .map_err(Error::IOError)?;
+            }
+            for record in self.records.values() {
+                let rec_str = serde_json::to_string(record).map_err(Error::JSONStringError)?;
+                writer
+                    .write_fmt(format_args!("{}\n", rec_str.as_str()))
+                    .map_err(Error::IOError)?;
+            }
+            writer.flush().map_err(Error::IOError)?;
+        }
+        f.sync_all().map_err(Error::IOError)?;
+        Ok(f)
     }
 
-    /*
-    pub fn remove(&self, uuid: UniqueId) -> Result<(), Error> {
-        unimplemented!()
+    /// Set the number of superseded lines (old versions of updated records, and deletions) that
+    /// the file may accumulate before it is compacted automatically. `None`, the default,
+    /// disables automatic compaction.
+    ///
+    /// If the file is already over the new threshold, it is compacted immediately. After that, a
+    /// failed automatic compaction does not fail the write that set it off; it is tried again
+    /// after the next write, and `compact` reports the error.
+    pub fn set_compaction_threshold(&mut self, threshold: Option<usize>) -> Result<(), Error> {
+        self.compaction_threshold = threshold;
+        for rollup in &mut self.rollups {
+            rollup.series.set_compaction_threshold(threshold)?;
+        }
+        self.compact_if_needed()
+    }
+
+    /// Set which records the series keeps. Any records now outside the policy are removed, and
+    /// the file compacted to drop them, before this returns.
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) -> Result<(), Error> {
+        self.retention = policy;
+        if self.expire(Utc::now()) > 0 {
+            self.compact()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remove the records outside the retention policy as of `now` from memory, returning how
+    /// many there were. They stay in the file until the next compaction.
+    fn expire(&mut self, now: DateTime<Utc>) -> usize {
+        let mut expired: Vec<UniqueId> = Vec::new();
+        if let Some(max_age) = self.retention.max_age {
+            // A cutoff from before the earliest time that can be represented expires nothing.
+            if let Some(cutoff) = now.checked_sub_signed(max_age) {
+                let cutoff = DateTimeTz(cutoff.with_timezone(&chrono_tz::Etc::UTC));
+                expired.extend(
+                    self.records
+                        .range(Interval::ending(cutoff, false))
+                        .map(|record| record.id.clone()),
+                );
+            }
+        }
+        if let Some(max_records) = self.retention.max_records {
+            let excess = self.records.len().saturating_sub(max_records);
+            expired.extend(
+                self.records
+                    .values()
+                    .take(excess)
+                    .map(|record| record.id.clone()),
+            );
+        }
+
+        let mut count = 0;
+        for id in expired {
+            if Arc::make_mut(&mut self.records).remove(&id).is_some() {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn compact_if_needed(&mut self) -> Result<(), Error> {
+        match self.compaction_threshold {
+            Some(threshold) if self.superseded_lines() > threshold => self.compact(),
+            _ => Ok(()),
+        }
     }
-    */
 }
 
-#[cfg(test)]
-mod tests {
-    extern crate chrono;
-    extern crate dimensioned;
+impl<T> Series<T, ReadOnly>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+{
+    /// Open an existing time series database for reading only. The file is never created or
+    /// modified, and the series takes a shared advisory lock on it, so that any number of readers
+    /// may have it open while no writer does. If a writer has it open, this returns
+    /// `Error::FileLocked`.
+    ///
+    /// A partially written final line is ignored, as with `Recovery::Truncate`, but left in the
+    /// file.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Series<T, ReadOnly>, Error> {
+        SeriesOptions::new().read_only().open(path)
+    }
+}
 
-    use self::chrono::prelude::*;
-    use self::dimensioned::si::{Kilogram, Meter, Second, KG, M, S};
-    use chrono_tz::Etc::UTC;
-    use date_time_tz::DateTimeTz;
-    use std::fs;
-    use std::ops;
+impl<T, A> Series<T, A>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    /// Open the file and load it according to the options. Anything specific to the access mode
+    /// is left to `SeriesOptions::open`.
+    pub(crate) fn open_with_options(
+        path: &Path,
+        options: &SeriesOptions<A>,
+    ) -> Result<Series<T, A>, Error> {
+        let open = || {
+            if A::WRITABLE {
+                OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .create(options.create)
+                    .open(path)
+            } else {
+                OpenOptions::new().read(true).open(path)
+            }
+        };
+        let f = open().map_err(Error::IOError)?;
 
-    use super::*;
-    use criteria::*;
+        let lock_mode = options.lock_mode.unwrap_or(A::DEFAULT_LOCK);
+        let f = lock_current(f, path, lock_mode, open)?;
 
-    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-    struct Distance(Meter<f64>);
+        let loaded = Series::<T, A>::load_file(&f, options.recovery, A::WRITABLE)?;
 
-    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-    struct Duration(Second<f64>);
+        let syncer = match options.sync_policy {
+            SyncPolicy::Interval(interval) if A::WRITABLE => Some(Syncer::start(
+                f.try_clone().map_err(Error::IOError)?,
+                interval,
+            )),
+            _ => None,
+        };
 
-    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-    struct BikeTrip {
-        datetime: DateTimeTz,
-        distance: Distance,
-        duration: Duration,
-        comments: String,
+        Ok(Series {
+            path: PathBuf::from(path),
+            file: A::handle(f),
+            lock_mode,
+            records: Arc::new(loaded.records),
+            log_records: loaded.log_records,
+            revision: loaded.revision,
+            offset: loaded.offset,
+            compaction_threshold: None,
+            retention: RetentionPolicy::default(),
+            torn_line: loaded.torn_line,
+            sync_policy: options.sync_policy,
+            unsynced_writes: 0,
+            syncer,
+            rollups: Vec::new(),
+            subscribers: Subscribers::new(),
+        })
     }
 
-    impl Recordable for BikeTrip {
-        fn timestamp(&self) -> DateTimeTz {
-            self.datetime.clone()
+    /// Load a file and return all of the records in it.
+    ///
+    /// Only the very last line of the file may be damaged, and only if it is missing its line
+    /// terminator; anything else is a corrupt file rather than an interrupted write, and is always
+    /// an error. The damage is repaired only if `writable` is set.
+    fn load_file(f: &File, recovery: Recovery, writable: bool) -> Result<Loaded<T>, Error> {
+        let mut records = Index::new();
+        let mut log_records = 0;
+        let mut revision = 0;
+        let mut offset: u64 = 0;
+        let mut reader = BufReader::new(f);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let len = reader
+                .read_until(b'\n', &mut line)
+                .map_err(Error::IOError)?;
+            if len == 0 {
+                break;
+            }
+
+            let terminated = line.last() == Some(&b'\n');
+            let parsed = parse_line::<T>(if terminated {
+                &line[..len - 1]
+            } else {
+                &line[..]
+            });
+            match parsed {
+                Ok(parsed) => {
+                    revision = cmp::max(revision, parsed.revision());
+                    for record in parsed.into_records() {
+                        apply_record(&mut records, record);
+                        log_records += 1;
+                    }
+                    if !terminated && writable {
+                        // The record made it to disk but its newline did not. Finish the line so
+                        // that the next append starts on a line of its own.
+                        let mut writer: &File = f;
+                        writer.write_all(b"\n").map_err(Error::IOError)?;
+                        offset += 1;
+                    }
+                }
+                Err(err) => {
+                    if terminated || recovery == Recovery::Strict {
+                        return Err(err);
+                    }
+                    if writable {
+                        f.set_len(offset).map_err(Error::IOError)?;
+                        f.sync_data().map_err(Error::IOError)?;
+                    }
+                    return Ok(Loaded {
+                        records,
+                        log_records,
+                        revision,
+                        offset,
+                        torn_line: Some(TornLine {
+                            offset,
+                            contents: String::from_utf8_lossy(&line).into_owned(),
+                        }),
+                    });
+                }
+            }
+            offset += len as u64;
         }
-        fn tags(&self) -> Vec<String> {
-            Vec::new()
+        Ok(Loaded {
+            records,
+            log_records,
+            revision,
+            offset,
+            torn_line: None,
+        })
+    }
+
+    /// Read and apply the lines that another process appended to the file since it was last read,
+    /// returning how many records they held. Subscribers are told about each change, and rollups
+    /// kept up to date, as if the changes had been made through this series. A final line that
+    /// is still being written is left for the next refresh.
+    ///
+    /// If the file was replaced or truncated, as happens when another process compacts it, the
+    /// whole file is read again instead, and the changes are worked out by comparing revisions.
+    ///
+    /// The writer holds an exclusive lock on the file, so a series that follows another process's
+    /// writes has to be opened with `LockMode::Unlocked`. Only one process may write to the file
+    /// at a time.
+    ///
+    /// ```text
+    /// let mut ts: Series<Reading, ReadOnly> = SeriesOptions::new()
+    ///     .read_only()
+    ///     .lock_mode(LockMode::Unlocked)
+    ///     .open("var/readings.json")?;
+    /// ts.refresh()?;
+    /// ```
+    pub fn refresh(&mut self) -> Result<usize, Error> {
+        let mut f = File::open(&self.path).map_err(Error::IOError)?;
+        let len = f.metadata().map_err(Error::IOError)?.len();
+        if len < self.offset || is_replaced(&f, A::file(&self.file))? {
+            return self.reload(f);
+        }
+        if len == self.offset {
+            return Ok(0);
         }
+
+        f.seek(SeekFrom::Start(self.offset))
+            .map_err(Error::IOError)?;
+        let mut reader = BufReader::new(f);
+        let mut line = Vec::new();
+        let mut count = 0;
+        loop {
+            line.clear();
+            let len = reader
+                .read_until(b'\n', &mut line)
+                .map_err(Error::IOError)?;
+            if len == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            if len == 1 {
+                // The newline that finishes a line which was read before it was terminated.
+                self.offset += 1;
+                continue;
+            }
+            // A line that fails to parse is left unread, so that every refresh reports it.
+            let parsed = parse_line::<T>(&line[..len - 1])?;
+            self.revision = cmp::max(self.revision, parsed.revision());
+            let mut changes = Vec::new();
+            for record in parsed.into_records() {
+                self.log_records += 1;
+                count += 1;
+                let id = record.id.clone();
+                let old = self.records.get(&id).cloned();
+                apply_record(Arc::make_mut(&mut self.records), record);
+                changes.push((old, self.records.get(&id).cloned()));
+            }
+            self.offset += len as u64;
+            for (old, new) in changes {
+                self.record_changed(old, new);
+            }
+        }
+        Ok(count)
     }
 
-    fn mk_trips() -> [BikeTrip; 5] {
-        [
-            BikeTrip {
-                datetime: DateTimeTz(UTC.ymd(2011, 10, 29).and_hms(0, 0, 0)),
-                distance: Distance(58741.055 * M),
-                duration: Duration(11040.0 * S),
-                comments: String::from("long time ago"),
-            },
-            BikeTrip {
-                datetime: DateTimeTz(UTC.ymd(2011, 10, 31).and_hms(0, 0, 0)),
-                distance: Distance(17702.0 * M),
-                duration: Duration(2880.0 * S),
-                comments: String::from("day 2"),
-            },
-            BikeTrip {
-                datetime: DateTimeTz(UTC.ymd(2011, 11, 02).and_hms(0, 0, 0)),
-                distance: Distance(41842.945 * M),
-                duration: Duration(7020.0 * S),
-                comments: String::from("Do Some Distance!"),
-            },
-            BikeTrip {
-                datetime: DateTimeTz(UTC.ymd(2011, 11, 04).and_hms(0, 0, 0)),
-                distance: Distance(34600.895 * M),
-                duration: Duration(5580.0 * S),
-                comments: String::from("I did a lot of distance back then"),
-            },
-            BikeTrip {
-                datetime: DateTimeTz(UTC.ymd(2011, 11, 05).and_hms(0, 0, 0)),
-                distance: Distance(6437.376 * M),
-                duration: Duration(960.0 * S),
+    /// Read the whole of a file that replaced the one the series had open, and switch over to it.
+    fn reload(&mut self, f: File) -> Result<usize, Error> {
+        let open = || {
+            if A::WRITABLE {
+                OpenOptions::new().read(true).append(true).open(&self.path)
+            } else {
+                OpenOptions::new().read(true).open(&self.path)
+            }
+        };
+        let f = if A::WRITABLE {
+            open().map_err(Error::IOError)?
+        } else {
+            f
+        };
+        let f = lock_current(f, &self.path, self.lock_mode, open)?;
+        let loaded = Series::<T, A>::load_file(&f, Recovery::Truncate, false)?;
+        if let Some(ref syncer) = self.syncer {
+            syncer.replace_file(f.try_clone().map_err(Error::IOError)?);
+        }
+
+        let old = mem::replace(&mut self.records, Arc::new(loaded.records));
+        self.file = A::handle(f);
+        self.log_records = loaded.log_records;
+        self.revision = cmp::max(self.revision, loaded.revision);
+        self.offset = loaded.offset;
+
+        let mut changes = Vec::new();
+        for record in self.records.values() {
+            match old.get(&record.id) {
+                Some(previous) if previous.revision == record.revision => (),
+                previous => changes.push((previous.cloned(), Some(record.clone()))),
+            }
+        }
+        for record in old.values() {
+            if self.records.get(&record.id).is_none() {
+                changes.push((Some(record.clone()), None));
+            }
+        }
+        let count = changes.len();
+        for (old, new) in changes {
+            self.record_changed(old, new);
+        }
+        Ok(count)
+    }
+
+    /// The time before which the retention policy may have expired records, if there is a
+    /// policy. Only the timestamp of the oldest record shows how far a limit on the number of
+    /// records has reached.
+    fn retention_cutoff(&self) -> Option<DateTimeTz> {
+        let by_age = self
+            .retention
+            .max_age
+            .and_then(|max_age| Utc::now().checked_sub_signed(max_age))
+            .map(|cutoff| DateTimeTz(cutoff.with_timezone(&chrono_tz::Etc::UTC)));
+        let by_count = match self.retention.max_records {
+            Some(_) => self
+                .records
+                .values()
+                .next()
+                .map(|record| record.timestamp()),
+            None => None,
+        };
+        cmp::max(by_age, by_count)
+    }
+
+    /// Bring the rollups up to date and notify the subscribers after a record changed from `old`
+    /// to `new`, either of which may be missing for a record that was added or deleted.
+    fn record_changed(&mut self, old: Option<Record<T>>, new: Option<Record<T>>) {
+        let cutoff = if self.rollups.is_empty() {
+            None
+        } else {
+            self.retention_cutoff()
+        };
+        for rollup in &mut self.rollups {
+            // The change is already in the file, so it has succeeded whatever becomes of the
+            // rollups. A rollup that fails to update is rebuilt after the next change, or at the
+            // next compaction.
+            let _ = rollup.record_changed(
+                &self.records,
+                cutoff.as_ref(),
+                old.as_ref().map(|r| &r.data),
+                new.as_ref().map(|r| &r.data),
+            );
+        }
+        if let Some(event) = Event::new(old, new) {
+            self.subscribers.notify(&event);
+        }
+    }
+
+    /// The partial line that was found at the end of the file when the series was opened, if there
+    /// was one. A writable series truncates it from the file; a read-only one skips over it.
+    pub fn torn_line(&self) -> Option<&TornLine> {
+        self.torn_line.as_ref()
+    }
+
+    /// The number of records in the file that no longer contribute to any live record, such as
+    /// superseded versions and deletions.
+    pub fn superseded_lines(&self) -> usize {
+        self.log_records - self.records.len()
+    }
+
+    /// The rollup with the given name, if one was added with `add_rollup`.
+    pub fn rollup(&self, name: &str) -> Option<&Series<RollupPoint>> {
+        self.rollups
+            .iter()
+            .find(|rollup| rollup.name == name)
+            .map(|rollup| &rollup.series)
+    }
+
+    /// Call `callback` with every change to the records that match the criteria, as it is made
+    /// through this series or picked up from the file by `refresh`. An update is delivered if
+    /// either the old or the new version of the record matches. Records expired by a retention
+    /// policy are not reported.
+    ///
+    /// Use `Interval::unbounded()` as the criteria to follow every change.
+    ///
+    /// ```text
+    /// ts.subscribe(Interval::unbounded(), |event: &Event<BikeTrip>| redraw(event));
+    /// ```
+    pub fn subscribe<C, F>(&mut self, criteria: C, callback: F) -> SubscriptionId
+    where
+        C: Criteria + Send + Sync + 'static,
+        F: FnMut(&Event<T>) + Send + 'static,
+    {
+        self.subscribe_where(criteria, |_: &T| true, callback)
+    }
+
+    /// Like `subscribe`, but only for the changes to records whose data also matches `payload`.
+    pub fn subscribe_where<C, P, F>(
+        &mut self,
+        criteria: C,
+        payload: P,
+        callback: F,
+    ) -> SubscriptionId
+    where
+        C: Criteria + Send + Sync + 'static,
+        P: PayloadCriteria<T> + Send + Sync + 'static,
+        F: FnMut(&Event<T>) + Send + 'static,
+    {
+        self.subscribers.add_callback(
+            Box::new(move |record| criteria.apply(record) && payload.apply_payload(&record.data)),
+            Box::new(callback),
+        )
+    }
+
+    /// Like `subscribe`, but deliver the changes to a channel. The subscription ends by itself
+    /// once the receiver is dropped.
+    pub fn subscribe_channel<C>(&mut self, criteria: C) -> (SubscriptionId, Receiver<Event<T>>)
+    where
+        C: Criteria + Send + Sync + 'static,
+    {
+        let (sender, receiver) = channel();
+        let id = self
+            .subscribers
+            .add_channel(Box::new(move |record| criteria.apply(record)), sender);
+        (id, receiver)
+    }
+
+    /// Cancel a subscription, returning whether it was still active.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.subscribers.remove(id)
+    }
+
+    /// Get all of the records in the database, in time order.
+    pub fn all_records(&self) -> Result<Vec<Record<T>>, Error> {
+        let results = self.records.values().cloned().collect();
+        Ok(results)
+    }
+
+    /// Iterate over all of the records in the database, in time order.
+    pub fn records<'s>(&'s self) -> Result<impl Iterator<Item = &'s Record<T>> + 's, Error> {
+        Ok(self.records.values())
+    }
+
+    /*  The point of having Search is so that a lot of internal optimizations can happen once the
+     *  data sets start getting large. */
+    /// Perform a search on the records in a database, based on the given criteria. The records
+    /// come back in time order.
+    ///
+    /// Only the records within the criteria's `time_bounds` are examined, so searches that
+    /// constrain the time take logarithmic time plus the size of the result. Searches with
+    /// `required_tags` examine only the records carrying the rarest of those tags.
+    pub fn search<C>(&self, criteria: C) -> Result<Vec<Record<T>>, Error>
+    where
+        C: Criteria,
+    {
+        let results: Vec<Record<T>> = self
+            .records
+            .search(criteria.time_bounds(), &criteria.required_tags())
+            .filter(|&tr| criteria.apply(tr))
+            .cloned()
+            .collect();
+        Ok(results)
+    }
+
+    /// Perform a search, as with `search`, that also requires the data of each record to match
+    /// `payload`: a closure over `&T`, or a `Field`. The payload is checked only for the records
+    /// that match the criteria, so narrow the search by time or tags wherever possible.
+    ///
+    /// ```text
+    /// let long_trips = ts.search_where(
+    ///     time_range(start, true, end, false),
+    ///     Field::new("/distance", Comparison::GreaterThan, 20000.0),
+    /// )?;
+    /// ```
+    pub fn search_where<C, P>(&self, criteria: C, payload: P) -> Result<Vec<Record<T>>, Error>
+    where
+        C: Criteria,
+        P: PayloadCriteria<T>,
+    {
+        let results: Vec<Record<T>> = self
+            .records
+            .search(criteria.time_bounds(), &criteria.required_tags())
+            .filter(|&tr| criteria.apply(tr) && payload.apply_payload(&tr.data))
+            .cloned()
+            .collect();
+        Ok(results)
+    }
+
+    /// Perform a search and sort the resulting records based on the comparison.
+    pub fn search_sorted<C, CMP>(&self, criteria: C, compare: CMP) -> Result<Vec<Record<T>>, Error>
+    where
+        C: Criteria,
+        CMP: FnMut(&Record<T>, &Record<T>) -> Ordering,
+    {
+        self.search_sorted_where(criteria, |_: &T| true, compare)
+    }
+
+    /// Perform a search with payload criteria, as with `search_where`, and sort the resulting
+    /// records based on the comparison.
+    pub fn search_sorted_where<C, P, CMP>(
+        &self,
+        criteria: C,
+        payload: P,
+        compare: CMP,
+    ) -> Result<Vec<Record<T>>, Error>
+    where
+        C: Criteria,
+        P: PayloadCriteria<T>,
+        CMP: FnMut(&Record<T>, &Record<T>) -> Ordering,
+    {
+        match self.search_where(criteria, payload) {
+            Ok(mut records) => {
+                records.sort_by(compare);
+                Ok(records)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Group the records matching the criteria into time buckets, and reduce the values that
+    /// `extract` takes from the records in each bucket. Only buckets that contain at least one
+    /// record are returned, in time order. Buckets follow the UTC calendar; use `aggregate_in` to
+    /// follow a local one.
+    ///
+    /// ```text
+    /// let weekly_distance = ts.aggregate(
+    ///     time_range(start, true, end, false),
+    ///     Bucket::Week,
+    ///     Reducer::Sum,
+    ///     |trip: &BikeTrip| trip.distance.value_unsafe,
+    /// )?;
+    /// ```
+    pub fn aggregate<C, F>(
+        &self,
+        criteria: C,
+        bucket: Bucket,
+        reducer: Reducer,
+        extract: F,
+    ) -> Result<Vec<Aggregate>, Error>
+    where
+        C: Criteria,
+        F: Fn(&T) -> f64,
+    {
+        self.aggregate_in(criteria, bucket, BucketZone::Utc, reducer, extract)
+    }
+
+    /// Like `aggregate`, but with buckets that follow the clock and calendar of `zone`.
+    ///
+    /// ```text
+    /// let daily_distance = ts.aggregate_in(
+    ///     time_range(start, true, end, false),
+    ///     Bucket::Day,
+    ///     BucketZone::Record,
+    ///     Reducer::Sum,
+    ///     |trip: &BikeTrip| trip.distance.value_unsafe,
+    /// )?;
+    /// ```
+    pub fn aggregate_in<C, F>(
+        &self,
+        criteria: C,
+        bucket: Bucket,
+        zone: BucketZone,
+        reducer: Reducer,
+        extract: F,
+    ) -> Result<Vec<Aggregate>, Error>
+    where
+        C: Criteria,
+        F: Fn(&T) -> f64,
+    {
+        self.aggregate_where(criteria, |_: &T| true, bucket, zone, reducer, extract)
+    }
+
+    /// Like `aggregate_in`, but only over the records whose data also matches `payload`.
+    ///
+    /// ```text
+    /// let weekly_long_rides = ts.aggregate_where(
+    ///     time_range(start, true, end, false),
+    ///     Field::new("/distance", Comparison::GreaterThan, 20000.0),
+    ///     Bucket::Week,
+    ///     BucketZone::Utc,
+    ///     Reducer::Count,
+    ///     |trip: &BikeTrip| trip.distance.value_unsafe,
+    /// )?;
+    /// ```
+    pub fn aggregate_where<C, P, F>(
+        &self,
+        criteria: C,
+        payload: P,
+        bucket: Bucket,
+        zone: BucketZone,
+        reducer: Reducer,
+        extract: F,
+    ) -> Result<Vec<Aggregate>, Error>
+    where
+        C: Criteria,
+        P: PayloadCriteria<T>,
+        F: Fn(&T) -> f64,
+    {
+        let records = self
+            .records
+            .search(criteria.time_bounds(), &criteria.required_tags())
+            .filter(|&tr| criteria.apply(tr) && payload.apply_payload(&tr.data));
+        aggregate::aggregate(records, bucket, zone, reducer, |record| {
+            extract(&record.data)
+        })
+    }
+
+    /// Get an exact record from the database based on unique id.
+    pub fn get(&self, uuid: &UniqueId) -> Result<Option<Record<T>>, Error> {
+        let val = self.records.get(uuid);
+        Ok(val.cloned())
+    }
+
+    /// Every version of a record that is in the file, including deletions, in the order they
+    /// were written. Compaction keeps only the latest version of each live record, so the history
+    /// reaches back no further than the last compaction.
+    pub fn history(&self, uuid: &UniqueId) -> Result<Vec<Version<T>>, Error> {
+        let mut versions = Vec::new();
+        for (write, line) in self.read_log()?.into_iter().enumerate() {
+            for record in line {
+                if record.id == *uuid {
+                    versions.push(Version {
+                        write,
+                        written_at: record.updated_at,
+                        revision: record.revision,
+                        data: record.data,
+                    });
+                }
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Reconstruct the records as they were at an earlier point, by replaying the lines of the
+    /// file up to that point. As with `history`, nothing from before the last compaction can be
+    /// reconstructed: a compacted file starts out with every record that was live at the time.
+    pub fn as_of(&self, point: AsOf) -> Result<Snapshot<T>, Error> {
+        let mut records = Index::new();
+        for (write, line) in self.read_log()?.into_iter().enumerate() {
+            for record in line {
+                if point.includes(write, record.updated_at.as_ref()) {
+                    apply_record(&mut records, record);
+                }
+            }
+        }
+        Ok(Snapshot::new(Arc::new(records)))
+    }
+
+    /// The records as they are now. The snapshot shares them with the series, which copies them
+    /// only if it is written to while the snapshot is still around.
+    pub(crate) fn snapshot(&self) -> Snapshot<T> {
+        Snapshot::new(Arc::clone(&self.records))
+    }
+
+    /// Read the records of every line of the file afresh. A partial final line, whether torn by a
+    /// crash or still being written, is ignored.
+    fn read_log(&self) -> Result<Vec<Vec<DeletableRecord<T>>>, Error> {
+        let mut reader = BufReader::new(File::open(&self.path).map_err(Error::IOError)?);
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let len = reader
+                .read_until(b'\n', &mut line)
+                .map_err(Error::IOError)?;
+            if len == 0 {
+                break;
+            }
+            let terminated = line.last() == Some(&b'\n');
+            match parse_line::<T>(if terminated {
+                &line[..len - 1]
+            } else {
+                &line[..]
+            }) {
+                Ok(parsed) => lines.push(parsed.into_records()),
+                Err(_) if !terminated => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(lines)
+    }
+
+    /*
+    pub fn remove(&self, uuid: UniqueId) -> Result<(), Error> {
+        unimplemented!()
+    }
+    */
+}
+
+/// Parse a single line of the file, without its line terminator.
+fn parse_line<T>(line: &[u8]) -> Result<LogLine<T>, Error>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+{
+    match str::from_utf8(line) {
+        Ok(line) => line.parse::<LogLine<T>>(),
+        Err(err) => Err(Error::IOError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            err,
+        ))),
+    }
+}
+
+/// Apply one record from the file to the set of live records.
+fn apply_record<T>(records: &mut Index<T>, record: DeletableRecord<T>)
+where
+    T: Clone + Recordable,
+{
+    match record.data {
+        Some(val) => {
+            records.insert(Record {
+                id: record.id,
+                data: val,
+                created_at: record.created_at,
+                updated_at: record.updated_at,
+                revision: record.revision,
+            });
+        }
+        None => {
+            records.remove(&record.id);
+        }
+    }
+}
+
+/// The time to stamp on a line written now. The file only records whole seconds, so the fraction
+/// is dropped here too, to keep the records in memory identical to what a reload would produce.
+fn write_time() -> DateTimeTz {
+    let now = Utc::now();
+    let now = now.with_nanosecond(0).unwrap_or(now);
+    DateTimeTz(now.with_timezone(&chrono_tz::Etc::UTC))
+}
+
+/// Whether the file now at the series' path is a different one from the file the series has open.
+#[cfg(unix)]
+fn is_replaced(current: &File, open: &File) -> Result<bool, Error> {
+    use std::os::unix::fs::MetadataExt;
+    let current = current.metadata().map_err(Error::IOError)?;
+    let open = open.metadata().map_err(Error::IOError)?;
+    Ok(current.dev() != open.dev() || current.ino() != open.ino())
+}
+
+#[cfg(not(unix))]
+fn is_replaced(_current: &File, _open: &File) -> Result<bool, Error> {
+    Ok(false)
+}
+
+/// Take an advisory lock on the file, without waiting for it.
+fn lock(f: &File, mode: LockMode) -> Result<(), Error> {
+    let res = match mode {
+        LockMode::Exclusive => f.try_lock_exclusive(),
+        LockMode::Shared => FileExt::try_lock_shared(f),
+        LockMode::Unlocked => return Ok(()),
+    };
+    res.map_err(|err| {
+        if err.kind() == fs2::lock_contended_error().kind() {
+            Error::FileLocked
+        } else {
+            Error::IOError(err)
+        }
+    })
+}
+
+/// Lock `f`, which was opened from `path` by `open`, and return the file that ends up locked.
+///
+/// A compaction in another process may rename a new file over `path` between the open and the
+/// lock. `f` is then an orphan that nobody else will ever read, and anything written to it is
+/// lost, so open the file again and retry until the locked file is the one at `path`.
+fn lock_current<O>(mut f: File, path: &Path, mode: LockMode, open: O) -> Result<File, Error>
+where
+    O: Fn() -> io::Result<File>,
+{
+    loop {
+        lock(&f, mode)?;
+        let current = File::open(path).map_err(Error::IOError)?;
+        if !is_replaced(&current, &f)? {
+            return Ok(f);
+        }
+        f = open().map_err(Error::IOError)?;
+    }
+}
+
+/// The path of the temporary file used while compacting the series at `path`.
+fn compaction_path(path: &Path) -> PathBuf {
+    let mut name = path
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(".compact");
+    path.with_file_name(name)
+}
+
+/// Flush the directory entry of a freshly renamed file to disk.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), Error> {
+    let dir = match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(Error::IOError)
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate chrono;
+    extern crate dimensioned;
+
+    use self::chrono::prelude::*;
+    use self::dimensioned::si::{Kilogram, Meter, Second, KG, M, S};
+    use chrono_tz::Etc::UTC;
+    use date_time_tz::DateTimeTz;
+
+    use super::*;
+    use criteria::*;
+    use shared::SharedSeries;
+
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    struct Distance(Meter<f64>);
+
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    struct Duration(Second<f64>);
+
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    struct BikeTrip {
+        datetime: DateTimeTz,
+        distance: Distance,
+        duration: Duration,
+        comments: String,
+    }
+
+    impl Recordable for BikeTrip {
+        fn timestamp(&self) -> DateTimeTz {
+            self.datetime.clone()
+        }
+        fn tags(&self) -> Vec<String> {
+            Vec::new()
+        }
+    }
+
+    fn mk_trips() -> [BikeTrip; 5] {
+        [
+            BikeTrip {
+                datetime: DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 29, 0, 0, 0).unwrap()),
+                distance: Distance(58741.055 * M),
+                duration: Duration(11040.0 * S),
+                comments: String::from("long time ago"),
+            },
+            BikeTrip {
+                datetime: DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap()),
+                distance: Distance(17702.0 * M),
+                duration: Duration(2880.0 * S),
+                comments: String::from("day 2"),
+            },
+            BikeTrip {
+                datetime: DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 2, 0, 0, 0).unwrap()),
+                distance: Distance(41842.945 * M),
+                duration: Duration(7020.0 * S),
+                comments: String::from("Do Some Distance!"),
+            },
+            BikeTrip {
+                datetime: DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 4, 0, 0, 0).unwrap()),
+                distance: Distance(34600.895 * M),
+                duration: Duration(5580.0 * S),
+                comments: String::from("I did a lot of distance back then"),
+            },
+            BikeTrip {
+                datetime: DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 5, 0, 0, 0).unwrap()),
+                distance: Distance(6437.376 * M),
+                duration: Duration(960.0 * S),
                 comments: String::from("day 5"),
             },
         ]
     }
 
-    fn run_test<T>(test: T) -> ()
-    where
-        T: FnOnce(tempfile::TempPath),
-    {
-        let tmp_file = tempfile::NamedTempFile::new().expect("temporary path created");
-        let tmp_path = tmp_file.into_temp_path();
-        test(tmp_path);
+    fn run_test<T>(test: T)
+    where
+        T: FnOnce(tempfile::TempPath),
+    {
+        let tmp_file = tempfile::NamedTempFile::new().expect("temporary path created");
+        let tmp_path = tmp_file.into_temp_path();
+        test(tmp_path);
+    }
+
+    #[test]
+    pub fn can_add_and_retrieve_entries() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let uuid = ts.put(trips[0].clone()).expect("expect a successful put");
+            let record_res = ts.get(&uuid);
+
+            for trip in &trips[1..=4] {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            match record_res {
+                Err(err) => panic!("{}", err),
+                Ok(None) => panic!("There should have been a value here"),
+                Ok(Some(tr)) => {
+                    assert_eq!(tr.id, uuid);
+                    assert_eq!(
+                        tr.timestamp(),
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 29, 0, 0, 0).unwrap())
+                    );
+                    assert_eq!(tr.data.duration, Duration(11040.0 * S));
+                    assert_eq!(tr.data.comments, String::from("long time ago"));
+                    assert_eq!(tr.data, trips[0]);
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn can_retrieve_entries_iterator() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            for trip in &trips[0..=4] {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            let as_vec = ts.all_records().expect("retrieval is currently infallible");
+            let as_iter = ts.records().expect("retrieval is currently infallible");
+
+            for (from_vec, from_iter) in as_vec.iter().zip(as_iter) {
+                assert_eq!(from_iter.id, from_vec.id);
+                assert_eq!(from_iter.data, from_vec.data);
+            }
+        })
+    }
+
+    #[test]
+    pub fn can_search_for_an_entry_with_exact_time() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            for trip in &trips[0..=4] {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            match ts.search(exact_time(DateTimeTz(
+                UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap(),
+            ))) {
+                Err(err) => panic!("{}", err),
+                Ok(v) => {
+                    assert_eq!(v.len(), 1);
+                    assert_eq!(v[0].data, trips[1]);
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn can_get_entries_in_time_range() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            for trip in &trips[0..=4] {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            match ts.search_sorted(
+                time_range(
+                    DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap()),
+                    true,
+                    DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 4, 0, 0, 0).unwrap()),
+                    true,
+                ),
+                |l, r| l.timestamp().cmp(&r.timestamp()),
+            ) {
+                Err(err) => panic!("{}", err),
+                Ok(v) => {
+                    assert_eq!(v.len(), 3);
+                    assert_eq!(v[0].data, trips[1]);
+                    assert_eq!(v[1].data, trips[2]);
+                    assert_eq!(v[2].data, trips[3]);
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn search_results_come_back_in_time_order() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            for trip in trips.iter().rev() {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            let v = ts
+                .search(And {
+                    lside: StartTime {
+                        time: DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap()),
+                        incl: false,
+                    },
+                    rside: Tags { tags: Vec::new() },
+                })
+                .expect("search should succeed");
+            assert_eq!(v.len(), 3);
+            assert_eq!(v[0].data, trips[2]);
+            assert_eq!(v[1].data, trips[3]);
+            assert_eq!(v[2].data, trips[4]);
+
+            let all = ts.all_records().expect("good record retrieval");
+            let in_order: Vec<BikeTrip> = all.into_iter().map(|r| r.data).collect();
+            assert_eq!(in_order, trips.to_vec());
+        })
+    }
+
+    #[test]
+    pub fn searches_on_the_payload() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            for trip in &trips {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            let v = ts
+                .search_where(Interval::unbounded(), |trip: &BikeTrip| {
+                    trip.distance.0 > 20000.0 * M
+                })
+                .expect("search should succeed");
+            assert_eq!(v.len(), 3);
+            assert_eq!(v[0].data, trips[0]);
+            assert_eq!(v[1].data, trips[2]);
+            assert_eq!(v[2].data, trips[3]);
+
+            let v = ts
+                .search_where(
+                    StartTime {
+                        time: DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap()),
+                        incl: true,
+                    },
+                    Field::new("/distance", Comparison::GreaterThan, 20000.0),
+                )
+                .expect("search should succeed");
+            assert_eq!(v.len(), 2);
+            assert_eq!(v[0].data, trips[2]);
+            assert_eq!(v[1].data, trips[3]);
+
+            let v = ts
+                .search_where(
+                    Interval::unbounded(),
+                    Field::new("/comments", Comparison::Equal, "day 2"),
+                )
+                .expect("search should succeed");
+            assert_eq!(v.len(), 1);
+            assert_eq!(v[0].data, trips[1]);
+
+            let v = ts
+                .search_where(
+                    Interval::unbounded(),
+                    Field::new("/no_such_field", Comparison::NotEqual, 0),
+                )
+                .expect("search should succeed");
+            assert!(v.is_empty());
+        })
+    }
+
+    #[test]
+    pub fn combines_payload_criteria_everywhere() {
+        use std::sync::{Arc, Mutex};
+        use subscription::Event;
+
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            let long = || Field::new("/distance", Comparison::GreaterThan, 20000.0);
+            let seen: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+            let log = seen.clone();
+            ts.subscribe_where(
+                Interval::unbounded(),
+                !long(),
+                move |event: &Event<BikeTrip>| {
+                    if let Event::Put(record) = event {
+                        log.lock().unwrap().push(record.data.comments.clone());
+                    }
+                },
+            );
+
+            for trip in &trips {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+            assert_eq!(*seen.lock().unwrap(), vec!["day 2", "day 5"]);
+
+            let v = ts
+                .search_sorted_where(
+                    Interval::unbounded(),
+                    long().or(exact_time(trips[1].datetime.clone())),
+                    |l, r| r.data.distance.0.partial_cmp(&l.data.distance.0).unwrap(),
+                )
+                .expect("search should succeed");
+            assert_eq!(
+                v.into_iter().map(|r| r.data).collect::<Vec<BikeTrip>>(),
+                vec![
+                    trips[0].clone(),
+                    trips[2].clone(),
+                    trips[3].clone(),
+                    trips[1].clone()
+                ]
+            );
+
+            let counts = ts
+                .aggregate_where(
+                    Interval::unbounded(),
+                    long(),
+                    Bucket::Month,
+                    BucketZone::Utc,
+                    Reducer::Count,
+                    |trip: &BikeTrip| trip.distance.0.value_unsafe,
+                )
+                .expect("aggregation should succeed");
+            assert_eq!(
+                counts.iter().map(|a| a.count).collect::<Vec<usize>>(),
+                vec![1, 2]
+            );
+
+            let snapshot = ts.as_of(AsOf::Write(3)).expect("expect a snapshot");
+            let v = snapshot.search_where(
+                StartTime {
+                    time: trips[1].datetime.clone(),
+                    incl: true,
+                },
+                long(),
+            );
+            assert_eq!(v.len(), 1);
+            assert_eq!(v[0].data, trips[2]);
+        })
+    }
+
+    #[test]
+    pub fn aggregates_matching_records() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            for trip in &trips {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            let months = ts
+                .aggregate(
+                    StartTime {
+                        time: DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 30, 0, 0, 0).unwrap()),
+                        incl: true,
+                    },
+                    Bucket::Month,
+                    Reducer::Sum,
+                    |trip| trip.distance.0.value_unsafe,
+                )
+                .expect("aggregation should succeed");
+            assert_eq!(months.len(), 2);
+            assert_eq!(
+                months[0].start,
+                DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 1, 0, 0, 0).unwrap())
+            );
+            assert_eq!(months[0].count, 1);
+            assert_eq!(months[0].value, 17702.0);
+            assert_eq!(
+                months[1].start,
+                DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 1, 0, 0, 0).unwrap())
+            );
+            assert_eq!(months[1].count, 3);
+        })
+    }
+
+    #[test]
+    pub fn persists_and_reads_an_entry() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+
+                for trip in &trips[0..=4] {
+                    ts.put(trip.clone()).expect("expect a successful put");
+                }
+            }
+
+            {
+                let ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                match ts.search_sorted(
+                    time_range(
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap()),
+                        true,
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 4, 0, 0, 0).unwrap()),
+                        true,
+                    ),
+                    |l, r| l.timestamp().cmp(&r.timestamp()),
+                ) {
+                    Err(err) => panic!("{}", err),
+                    Ok(v) => {
+                        assert_eq!(v.len(), 3);
+                        assert_eq!(v[0].data, trips[1]);
+                        assert_eq!(v[1].data, trips[2]);
+                        assert_eq!(v[2].data, trips[3]);
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn can_write_to_existing_file() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+
+                for trip in &trips[0..=2] {
+                    ts.put(trip.clone()).expect("expect a successful put");
+                }
+            }
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                match ts.search_sorted(
+                    time_range(
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap()),
+                        true,
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 4, 0, 0, 0).unwrap()),
+                        true,
+                    ),
+                    |l, r| l.timestamp().cmp(&r.timestamp()),
+                ) {
+                    Err(err) => panic!("{}", err),
+                    Ok(v) => {
+                        assert_eq!(v.len(), 2);
+                        assert_eq!(v[0].data, trips[1]);
+                        assert_eq!(v[1].data, trips[2]);
+                        ts.put(trips[3].clone()).expect("expect a successful put");
+                        ts.put(trips[4].clone()).expect("expect a successful put");
+                    }
+                }
+            }
+
+            {
+                let ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                match ts.search_sorted(
+                    time_range(
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 10, 31, 0, 0, 0).unwrap()),
+                        true,
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 5, 0, 0, 0).unwrap()),
+                        true,
+                    ),
+                    |l, r| l.timestamp().cmp(&r.timestamp()),
+                ) {
+                    Err(err) => panic!("{}", err),
+                    Ok(v) => {
+                        assert_eq!(v.len(), 4);
+                        assert_eq!(v[0].data, trips[1]);
+                        assert_eq!(v[1].data, trips[2]);
+                        assert_eq!(v[2].data, trips[3]);
+                        assert_eq!(v[3].data, trips[4]);
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn can_overwrite_existing_entry() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+
+            ts.put(trips[0].clone()).expect("expect a successful put");
+            ts.put(trips[1].clone()).expect("expect a successful put");
+            let trip_id = ts.put(trips[2].clone()).expect("expect a successful put");
+
+            match ts.get(&trip_id) {
+                Err(err) => panic!("{}", err),
+                Ok(None) => panic!("record not found"),
+                Ok(Some(mut trip)) => {
+                    trip.data.distance = Distance(50000.0 * M);
+                    ts.update(trip).expect("expect record to update");
+                }
+            };
+
+            match ts.get(&trip_id) {
+                Err(err) => panic!("{}", err),
+                Ok(None) => panic!("record not found"),
+                Ok(Some(trip)) => {
+                    assert_eq!(
+                        trip.data.datetime,
+                        DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 2, 0, 0, 0).unwrap())
+                    );
+                    assert_eq!(trip.data.distance, Distance(50000.0 * M));
+                    assert_eq!(trip.data.duration, Duration(7020.0 * S));
+                    assert_eq!(trip.data.comments, String::from("Do Some Distance!"));
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn record_overwrites_get_persisted() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+
+                ts.put(trips[0].clone()).expect("expect a successful put");
+                ts.put(trips[1].clone()).expect("expect a successful put");
+                let trip_id = ts.put(trips[2].clone()).expect("expect a successful put");
+
+                match ts.get(&trip_id) {
+                    Err(err) => panic!("{}", err),
+                    Ok(None) => panic!("record not found"),
+                    Ok(Some(mut trip)) => {
+                        trip.data.distance = Distance(50000.0 * M);
+                        ts.update(trip).expect("expect record to update");
+                    }
+                };
+            }
+
+            {
+                let ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+
+                match ts.all_records() {
+                    Err(err) => panic!("{}", err),
+                    Ok(trips) => assert_eq!(trips.len(), 3),
+                }
+
+                match ts.search(exact_time(DateTimeTz(
+                    UTC.with_ymd_and_hms(2011, 11, 2, 0, 0, 0).unwrap(),
+                ))) {
+                    Err(err) => panic!("{}", err),
+                    Ok(trips) => {
+                        assert_eq!(trips.len(), 1);
+                        assert_eq!(
+                            trips[0].data.datetime,
+                            DateTimeTz(UTC.with_ymd_and_hms(2011, 11, 2, 0, 0, 0).unwrap())
+                        );
+                        assert_eq!(trips[0].data.distance, Distance(50000.0 * M));
+                        assert_eq!(trips[0].data.duration, Duration(7020.0 * S));
+                        assert_eq!(trips[0].data.comments, String::from("Do Some Distance!"));
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn can_delete_an_entry() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+                ts.put(trips[1].clone()).expect("expect a successful put");
+                ts.put(trips[2].clone()).expect("expect a successful put");
+
+                ts.delete(&trip_id).expect("successful delete");
+
+                let recs = ts.all_records().expect("good record retrieval");
+                assert_eq!(recs.len(), 2);
+            }
+
+            {
+                let ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                let recs = ts.all_records().expect("good record retrieval");
+                assert_eq!(recs.len(), 2);
+            }
+        })
+    }
+
+    #[test]
+    pub fn stamps_records_as_they_are_written() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let (trip_id, created_at) = {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+                ts.put(trips[1].clone()).expect("expect a successful put");
+
+                let trip = ts.get(&trip_id).unwrap().unwrap();
+                assert_eq!(trip.revision(), 1);
+                assert!(trip.created_at().is_some());
+                assert_eq!(trip.created_at(), trip.updated_at());
+
+                let created_at = trip.created_at().cloned();
+                ts.update(trip).expect("expect record to update");
+                let trip = ts.get(&trip_id).unwrap().unwrap();
+                assert_eq!(trip.revision(), 3);
+                assert_eq!(trip.created_at().cloned(), created_at);
+                assert!(trip.updated_at().cloned() >= created_at);
+                (trip_id, created_at)
+            };
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let trip = ts.get(&trip_id).unwrap().unwrap();
+            assert_eq!(trip.revision(), 3);
+            assert_eq!(trip.created_at().cloned(), created_at);
+
+            let id = ts.put(trips[2].clone()).expect("expect a successful put");
+            assert_eq!(ts.get(&id).unwrap().unwrap().revision(), 4);
+        })
+    }
+
+    #[test]
+    pub fn insert_update_and_upsert_check_for_the_record() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let record = Record::new(trips[0].clone());
+            let id = record.id.clone();
+
+            match ts.update(record.clone()) {
+                Err(Error::NotFound(ref missing)) if *missing == id => (),
+                other => panic!("expected NotFound, got {:?}", other),
+            }
+            ts.insert(record.clone())
+                .expect("a new record should insert");
+            match ts.insert(record.clone()) {
+                Err(Error::AlreadyExists(ref existing)) if *existing == id => (),
+                other => panic!("expected AlreadyExists, got {:?}", other),
+            }
+            ts.update(record.clone())
+                .expect("an existing record should update");
+            assert_eq!(count_lines(&path), 2);
+
+            let other = Record::new(trips[1].clone());
+            ts.upsert(other.clone())
+                .expect("upsert should add a new record");
+            ts.upsert(other.clone())
+                .expect("upsert should update an existing record");
+            assert_eq!(ts.all_records().unwrap().len(), 2);
+            assert_eq!(count_lines(&path), 4);
+
+            assert!(ts.delete(&id).expect("successful delete"));
+            assert!(!ts.delete(&id).expect("successful delete"));
+            assert_eq!(count_lines(&path), 5);
+        })
+    }
+
+    #[test]
+    pub fn batches_commit_as_a_single_line() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let (first_id, second_id) = {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                let first_id = ts.put(trips[0].clone()).expect("expect a successful put");
+                let mut first = ts.get(&first_id).unwrap().unwrap();
+
+                let mut batch = ts.batch();
+                let second_id = batch.put(trips[1].clone());
+                batch.put(trips[2].clone());
+                first.data.comments = String::from("revised");
+                batch
+                    .update(first)
+                    .expect("a live record should stage an update");
+                assert!(batch.delete(&second_id));
+                assert!(!batch.delete(&second_id));
+                batch
+                    .insert(Record::new(trips[3].clone()))
+                    .expect("a new record should stage an insert");
+                assert_eq!(batch.len(), 5);
+                batch.commit().expect("the batch should commit");
+
+                assert_eq!(count_lines(&path), 2);
+                assert_eq!(ts.all_records().unwrap().len(), 3);
+                assert_eq!(ts.superseded_lines(), 3);
+                (first_id, second_id)
+            };
+
+            let ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            assert_eq!(ts.all_records().unwrap().len(), 3);
+            let first = ts.get(&first_id).unwrap().unwrap();
+            assert_eq!(first.data.comments, "revised");
+            assert_eq!(first.revision(), 4);
+            assert!(ts.get(&second_id).unwrap().is_none());
+            let history = ts.history(&second_id).unwrap();
+            assert_eq!(history.len(), 2);
+            assert!(history.iter().all(|v| v.write == 1));
+        })
+    }
+
+    #[test]
+    pub fn batches_roll_back_when_dropped_or_torn() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                ts.put(trips[0].clone()).expect("expect a successful put");
+
+                {
+                    let mut batch = ts.batch();
+                    batch.put(trips[1].clone());
+                    batch.put(trips[2].clone());
+                }
+                let mut batch = ts.batch();
+                batch.put(trips[3].clone());
+                batch.rollback();
+                assert_eq!(ts.all_records().unwrap().len(), 1);
+                assert_eq!(count_lines(&path), 1);
+
+                let mut batch = ts.batch();
+                batch.put(trips[1].clone());
+                batch.put(trips[2].clone());
+                batch.commit().expect("the batch should commit");
+            }
+
+            // Cut the batch off partway through, as a crash in the middle of the write would.
+            let len = std::fs::metadata(&path).unwrap().len();
+            OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_len(len - 20)
+                .unwrap();
+
+            let ts: Series<BikeTrip> =
+                Series::open(&path).expect("a torn batch should not prevent opening");
+            assert!(ts.torn_line().is_some());
+            assert_eq!(ts.all_records().unwrap().len(), 1);
+        })
+    }
+
+    #[test]
+    pub fn failed_writes_leave_the_records_alone() {
+        // JSON cannot hold a map with keys that are not strings, so any record with something in
+        // `readings` fails to serialize.
+        #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+        struct Sample {
+            time: DateTimeTz,
+            readings: HashMap<Vec<u8>, u8>,
+        }
+
+        impl Recordable for Sample {
+            fn timestamp(&self) -> DateTimeTz {
+                self.time.clone()
+            }
+
+            fn tags(&self) -> Vec<String> {
+                Vec::new()
+            }
+        }
+
+        run_test(|path| {
+            let good = Sample {
+                time: DateTimeTz(UTC.with_ymd_and_hms(2019, 5, 15, 12, 0, 0).unwrap()),
+                readings: HashMap::new(),
+            };
+            let mut bad = good.clone();
+            bad.readings.insert(vec![1], 1);
+
+            let mut ts: Series<Sample> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let id = ts.put(good.clone()).expect("expect a successful put");
+
+            assert!(ts.put(bad.clone()).is_err());
+            let mut record = ts.get(&id).unwrap().unwrap();
+            record.data = bad.clone();
+            assert!(ts.update(record).is_err());
+
+            let mut batch = ts.batch();
+            batch.delete(&id);
+            batch.put(good.clone());
+            batch.put(bad);
+            assert!(batch.commit().is_err());
+
+            let records = ts.all_records().unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].id, id);
+            assert_eq!(records[0].data, good);
+            assert_eq!(count_lines(&path), 1);
+        })
+    }
+
+    #[test]
+    pub fn shares_a_series_between_threads() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<SharedSeries<BikeTrip>>();
+        assert_send_sync::<SharedSeries<BikeTrip, ReadOnly>>();
+
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let shared = SharedSeries::new(
+                Series::open(&path).expect("expect the time series to open correctly"),
+            );
+            let writers: Vec<std::thread::JoinHandle<()>> = trips
+                .iter()
+                .cloned()
+                .map(|trip| {
+                    let shared = shared.clone();
+                    std::thread::spawn(move || {
+                        shared.write().put(trip).expect("expect a successful put");
+                    })
+                })
+                .collect();
+            let readers: Vec<std::thread::JoinHandle<usize>> = (0..3)
+                .map(|_| {
+                    let shared = shared.clone();
+                    std::thread::spawn(move || shared.read().all_records().len())
+                })
+                .collect();
+
+            for writer in writers {
+                writer.join().expect("the writer should not panic");
+            }
+            for reader in readers {
+                assert!(reader.join().expect("the reader should not panic") <= 5);
+            }
+            assert_eq!(shared.read().all_records().len(), 5);
+            assert_eq!(count_lines(&path), 5);
+        })
+    }
+
+    #[test]
+    pub fn reads_do_not_wait_for_a_write_in_progress() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let shared = SharedSeries::new(
+                Series::open(&path).expect("expect the time series to open correctly"),
+            );
+            shared
+                .write()
+                .put(trips[0].clone())
+                .expect("expect a successful put");
+
+            let (held, holding) = channel();
+            let (release, released) = channel::<()>();
+            let writer = {
+                let shared = shared.clone();
+                let trip = trips[1].clone();
+                std::thread::spawn(move || {
+                    let mut series = shared.write();
+                    series.put(trip).expect("expect a successful put");
+                    held.send(()).unwrap();
+                    // Stand in for a slow sync or compaction with the write guard still held.
+                    released.recv().unwrap();
+                })
+            };
+            holding.recv().unwrap();
+            assert_eq!(count_lines(&path), 2);
+
+            let (done, finished) = channel();
+            let reader = shared.clone();
+            std::thread::spawn(move || done.send(reader.read().all_records().len()).unwrap());
+            assert_eq!(
+                finished.recv_timeout(std::time::Duration::from_secs(5)),
+                Ok(1),
+                "the read should finish, without the write in progress"
+            );
+
+            release.send(()).unwrap();
+            writer.join().expect("the writer should not panic");
+            assert_eq!(shared.read().all_records().len(), 2);
+        })
+    }
+
+    /// Run a future to completion on the current thread, parking it until the future is woken.
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future + Unpin>(mut future: F) -> F::Output {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+        use std::thread::Thread;
+
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+        fn clone(data: *const ()) -> RawWaker {
+            let thread = unsafe { &*(data as *const Thread) }.clone();
+            RawWaker::new(Box::into_raw(Box::new(thread)) as *const (), &VTABLE)
+        }
+        fn wake(data: *const ()) {
+            unsafe { Box::from_raw(data as *mut Thread) }.unpark();
+        }
+        fn wake_by_ref(data: *const ()) {
+            unsafe { &*(data as *const Thread) }.unpark();
+        }
+        fn drop(data: *const ()) {
+            std::mem::drop(unsafe { Box::from_raw(data as *mut Thread) });
+        }
+
+        let current = std::thread::current();
+        let waker = unsafe { Waker::from_raw(clone(&current as *const Thread as *const ())) };
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::new(&mut future).poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    pub fn runs_operations_asynchronously() {
+        use async_series::AsyncSeries;
+
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let ts: AsyncSeries<BikeTrip> = block_on(AsyncSeries::open(&path))
+                    .expect("expect the time series to open correctly");
+                let pending: Vec<_> = trips.iter().cloned().map(|trip| ts.put(trip)).collect();
+                let ids: Vec<UniqueId> = pending
+                    .into_iter()
+                    .map(|put| block_on(put).expect("expect a successful put"))
+                    .collect();
+
+                let mut trip = block_on(ts.get(&ids[0])).unwrap().unwrap();
+                trip.data.comments = String::from("revised");
+                block_on(ts.update(trip)).expect("expect record to update");
+                assert!(block_on(ts.delete(&ids[1])).unwrap());
+                assert_eq!(
+                    block_on(ts.search(time_range(
+                        trips[0].datetime.clone(),
+                        true,
+                        trips[2].datetime.clone(),
+                        true,
+                    )))
+                    .unwrap()
+                    .len(),
+                    2
+                );
+                assert_eq!(
+                    block_on(ts.with(|series| Ok(series.superseded_lines()))).unwrap(),
+                    3
+                );
+
+                let other = ts.clone();
+                block_on(ts.close()).expect("the series should close");
+                assert!(block_on(other.all_records()).is_err());
+            }
+
+            let ts: Series<BikeTrip> =
+                Series::open(&path).expect("the worker should release the series when dropped");
+            assert_eq!(ts.all_records().unwrap().len(), 4);
+        })
+    }
+
+    #[test]
+    pub fn delivers_changes_to_subscribers() {
+        use std::sync::{Arc, Mutex};
+        use subscription::Event;
+
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let seen: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+            let log = seen.clone();
+            let everything = ts.subscribe(Interval::unbounded(), move |event: &Event<BikeTrip>| {
+                log.lock().unwrap().push(match event {
+                    Event::Put(record) => format!("put {}", record.data.comments),
+                    Event::Update { old, new } => {
+                        format!("update {} -> {}", old.data.comments, new.data.comments)
+                    }
+                    Event::Delete(record) => format!("delete {}", record.data.comments),
+                })
+            });
+            let (november, events) = ts.subscribe_channel(time_range(
+                trips[2].datetime.clone(),
+                true,
+                trips[4].datetime.clone(),
+                true,
+            ));
+
+            let first_id = ts.put(trips[0].clone()).expect("expect a successful put");
+            let third_id = ts.put(trips[2].clone()).expect("expect a successful put");
+            let mut first = ts.get(&first_id).unwrap().unwrap();
+            first.data.comments = String::from("revised");
+            ts.update(first).expect("expect record to update");
+
+            let mut batch = ts.batch();
+            batch.put(trips[3].clone());
+            batch.delete(&third_id);
+            batch.commit().expect("the batch should commit");
+
+            assert_eq!(
+                *seen.lock().unwrap(),
+                vec![
+                    "put long time ago",
+                    "put Do Some Distance!",
+                    "update long time ago -> revised",
+                    "put I did a lot of distance back then",
+                    "delete Do Some Distance!",
+                ]
+            );
+
+            let november_events: Vec<Event<BikeTrip>> = events.try_iter().collect();
+            assert_eq!(november_events.len(), 3);
+            match november_events[0] {
+                Event::Put(ref record) => assert_eq!(record.id, third_id),
+                ref other => panic!("expected a put, got {:?}", other),
+            }
+            match november_events[2] {
+                Event::Delete(ref record) => {
+                    assert_eq!(record.id, third_id);
+                    assert_eq!(record.revision(), 2);
+                }
+                ref other => panic!("expected a delete, got {:?}", other),
+            }
+
+            assert!(ts.unsubscribe(everything));
+            assert!(!ts.unsubscribe(everything));
+            drop(events);
+            ts.put(trips[4].clone()).expect("expect a successful put");
+            assert!(!ts.unsubscribe(november));
+            assert_eq!(seen.lock().unwrap().len(), 5);
+        })
+    }
+
+    #[test]
+    pub fn compare_and_update_refuses_stale_records() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+
+            let mut first = ts.get(&trip_id).unwrap().unwrap();
+            let mut second = first.clone();
+
+            first.data.comments = String::from("first edit");
+            let revision = first.revision();
+            ts.compare_and_update(first, revision)
+                .expect("an unchanged record should update");
+
+            second.data.comments = String::from("second edit");
+            let revision = second.revision();
+            match ts.compare_and_update(second, revision) {
+                Err(Error::RevisionConflict {
+                    expected: 1,
+                    actual: Some(2),
+                }) => (),
+                other => panic!("expected a revision conflict, got {:?}", other),
+            }
+            assert_eq!(
+                ts.get(&trip_id).unwrap().unwrap().data.comments,
+                "first edit"
+            );
+            assert_eq!(count_lines(&path), 2);
+
+            let stale = ts.get(&trip_id).unwrap().unwrap();
+            ts.delete(&trip_id).expect("successful delete");
+            match ts.compare_and_update(stale, 2) {
+                Err(Error::RevisionConflict {
+                    expected: 2,
+                    actual: None,
+                }) => (),
+                other => panic!("expected a revision conflict, got {:?}", other),
+            }
+            assert!(ts.get(&trip_id).unwrap().is_none());
+        })
+    }
+
+    #[test]
+    pub fn lists_the_history_of_a_record() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+            ts.put(trips[1].clone()).expect("expect a successful put");
+            let mut trip = ts.get(&trip_id).unwrap().unwrap();
+            trip.data.comments = String::from("revised");
+            ts.update(trip).expect("expect record to update");
+            ts.delete(&trip_id).expect("successful delete");
+
+            let history = ts.history(&trip_id).expect("history should be readable");
+            assert_eq!(
+                history.iter().map(|v| v.write).collect::<Vec<usize>>(),
+                vec![0, 2, 3]
+            );
+            assert_eq!(history[0].data, Some(trips[0].clone()));
+            assert_eq!(
+                history[1].data.as_ref().map(|trip| trip.comments.as_str()),
+                Some("revised")
+            );
+            assert_eq!(history[2].data, None);
+            assert!(history.iter().all(|v| v.written_at.is_some()));
+            assert_eq!(
+                history.iter().map(|v| v.revision).collect::<Vec<u64>>(),
+                vec![1, 3, 4]
+            );
+
+            ts.compact().expect("compaction should succeed");
+            assert_eq!(ts.history(&trip_id).unwrap(), vec![]);
+        })
+    }
+
+    #[test]
+    pub fn reconstructs_earlier_states() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+            ts.put(trips[1].clone()).expect("expect a successful put");
+            ts.delete(&trip_id).expect("successful delete");
+
+            let before_delete = ts.as_of(AsOf::Write(2)).unwrap();
+            assert_eq!(before_delete.all_records().len(), 2);
+            assert_eq!(
+                before_delete.get(&trip_id).map(|record| record.data),
+                Some(trips[0].clone())
+            );
+            assert_eq!(
+                before_delete
+                    .search(exact_time(trips[0].datetime.clone()))
+                    .len(),
+                1
+            );
+            assert_eq!(ts.as_of(AsOf::Write(0)).unwrap().all_records().len(), 0);
+            assert_eq!(ts.as_of(AsOf::Write(10)).unwrap().all_records().len(), 1);
+
+            let now = DateTimeTz(UTC.from_utc_datetime(&Utc::now().naive_utc()));
+            assert_eq!(ts.as_of(AsOf::Time(now)).unwrap().all_records().len(), 1);
+            let long_ago = DateTimeTz(UTC.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap());
+            assert_eq!(
+                ts.as_of(AsOf::Time(long_ago)).unwrap().all_records().len(),
+                0
+            );
+        })
+    }
+
+    fn months<A: Access>(rollup: &Series<RollupPoint, A>) -> Vec<(u32, usize, f64)> {
+        rollup
+            .all_records()
+            .expect("good record retrieval")
+            .into_iter()
+            .map(|r| (r.data.start.0.month(), r.data.count, r.data.sum))
+            .collect()
+    }
+
+    #[test]
+    pub fn writes_succeed_when_a_rollup_fails() {
+        run_test(|path| {
+            let rollup_path = tempfile::NamedTempFile::new()
+                .expect("temporary path created")
+                .into_temp_path();
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            ts.set_sync_policy(SyncPolicy::Always)
+                .expect("expect the policy to be set");
+            ts.add_rollup(
+                "monthly",
+                &rollup_path,
+                Bucket::Month,
+                BucketZone::Utc,
+                |trip: &BikeTrip| trip.duration.0.value_unsafe,
+            )
+            .expect("rollup should be added");
+            let (_, events) = ts.subscribe_channel(Interval::unbounded());
+
+            // Writes to the rollup file now fail.
+            ts.rollups[0].series.file =
+                LineWriter::new(File::open(&rollup_path).expect("expect the rollup file to open"));
+            ts.put(trips[0].clone())
+                .expect("the put should succeed although the rollup fails");
+            assert_eq!(count_lines(&path), 1);
+            assert_eq!(ts.unsynced_writes, 0);
+            assert!(events.try_recv().is_ok());
+            assert!(ts.rollups[0].is_stale());
+
+            ts.rollups[0].series.file = LineWriter::new(
+                OpenOptions::new()
+                    .append(true)
+                    .open(&rollup_path)
+                    .expect("expect the rollup file to open"),
+            );
+            ts.put(trips[2].clone()).expect("expect a successful put");
+            assert!(!ts.rollups[0].is_stale());
+            assert_eq!(
+                months(ts.rollup("monthly").unwrap()),
+                vec![(10, 1, 11040.0), (11, 1, 7020.0)]
+            );
+        })
+    }
+
+    #[test]
+    pub fn rollups_leave_out_values_that_are_not_finite() {
+        run_test(|path| {
+            let rollup_path = tempfile::NamedTempFile::new()
+                .expect("temporary path created")
+                .into_temp_path();
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let extract = |trip: &BikeTrip| match trip.comments.as_str() {
+                "day 2" => (-1.0f64).sqrt(),
+                "day 5" => 1.0 / 0.0,
+                _ => trip.duration.0.value_unsafe,
+            };
+            ts.add_rollup(
+                "monthly",
+                &rollup_path,
+                Bucket::Month,
+                BucketZone::Utc,
+                extract,
+            )
+            .expect("rollup should be added");
+            for trip in &trips {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+            assert_eq!(
+                months(ts.rollup("monthly").unwrap()),
+                vec![(10, 1, 11040.0), (11, 2, 12600.0)]
+            );
+
+            // The points can be read back.
+            ts.add_rollup(
+                "monthly",
+                &rollup_path,
+                Bucket::Month,
+                BucketZone::Utc,
+                extract,
+            )
+            .expect("rollup should be added again");
+            assert_eq!(
+                months(ts.rollup("monthly").unwrap()),
+                vec![(10, 1, 11040.0), (11, 2, 12600.0)]
+            );
+        })
+    }
+
+    #[test]
+    pub fn rollups_follow_puts_updates_and_deletes() {
+        run_test(|path| {
+            let rollup_path = tempfile::NamedTempFile::new()
+                .expect("temporary path created")
+                .into_temp_path();
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            ts.add_rollup(
+                "monthly",
+                &rollup_path,
+                Bucket::Month,
+                BucketZone::Utc,
+                |trip: &BikeTrip| trip.duration.0.value_unsafe,
+            )
+            .expect("rollup should be added");
+
+            let first_id = ts.put(trips[0].clone()).expect("expect a successful put");
+            for trip in &trips[1..] {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+            assert_eq!(
+                months(ts.rollup("monthly").unwrap()),
+                vec![(10, 2, 13920.0), (11, 3, 13560.0)]
+            );
+
+            let mut moved = ts.get(&first_id).unwrap().unwrap();
+            moved.data.datetime = DateTimeTz(UTC.with_ymd_and_hms(2011, 12, 1, 0, 0, 0).unwrap());
+            ts.update(moved).expect("expect a successful update");
+            assert_eq!(
+                months(ts.rollup("monthly").unwrap()),
+                vec![(10, 1, 2880.0), (11, 3, 13560.0), (12, 1, 11040.0)]
+            );
+
+            ts.delete(&first_id).expect("expect a successful delete");
+            let points = ts.rollup("monthly").unwrap().all_records().unwrap();
+            assert_eq!(points.len(), 2);
+            assert_eq!(points[1].data.min, 960.0);
+            assert_eq!(points[1].data.max, 7020.0);
+            assert_eq!(points[1].data.mean, 13560.0 / 3.0);
+            assert_eq!(points[1].data.first, 7020.0);
+            assert_eq!(points[1].data.last, 960.0);
+            assert!(ts.rollup("weekly").is_none());
+
+            let empty_path = tempfile::NamedTempFile::new()
+                .expect("temporary path created")
+                .into_temp_path();
+            match ts.add_rollup(
+                "empty",
+                &empty_path,
+                Bucket::Fixed(chrono::Duration::zero()),
+                BucketZone::Utc,
+                |trip: &BikeTrip| trip.duration.0.value_unsafe,
+            ) {
+                Err(Error::InvalidBucket) => (),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(()) => panic!("a rollup with empty buckets should be rejected"),
+            }
+            assert!(ts.rollup("empty").is_none());
+        })
+    }
+
+    #[test]
+    pub fn rollups_catch_up_when_added() {
+        run_test(|path| {
+            let rollup_path = tempfile::NamedTempFile::new()
+                .expect("temporary path created")
+                .into_temp_path();
+            let trips = mk_trips();
+            let add_rollup = |ts: &mut Series<BikeTrip>| {
+                ts.add_rollup(
+                    "monthly",
+                    &rollup_path,
+                    Bucket::Month,
+                    BucketZone::Utc,
+                    |trip: &BikeTrip| trip.duration.0.value_unsafe,
+                )
+                .expect("rollup should be added");
+            };
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                for trip in &trips[0..3] {
+                    ts.put(trip.clone()).expect("expect a successful put");
+                }
+                add_rollup(&mut ts);
+                assert_eq!(
+                    months(ts.rollup("monthly").unwrap()),
+                    vec![(10, 2, 13920.0), (11, 1, 7020.0)]
+                );
+            }
+
+            {
+                // Changes made while the rollup is not attached get picked up when it is.
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                let october = ts.search(exact_time(trips[0].datetime.clone())).unwrap();
+                ts.delete(&october[0].id)
+                    .expect("expect a successful delete");
+                ts.put(trips[3].clone()).expect("expect a successful put");
+                add_rollup(&mut ts);
+                assert_eq!(
+                    months(ts.rollup("monthly").unwrap()),
+                    vec![(10, 1, 2880.0), (11, 2, 12600.0)]
+                );
+            }
+
+            let rollup: Series<RollupPoint, ReadOnly> =
+                Series::open_read_only(&rollup_path).expect("the rollup should open on its own");
+            assert_eq!(months(&rollup), vec![(10, 1, 2880.0), (11, 2, 12600.0)]);
+        })
+    }
+
+    #[test]
+    pub fn rollups_keep_old_points_only_under_retention() {
+        run_test(|path| {
+            let rollup_path = tempfile::NamedTempFile::new()
+                .expect("temporary path created")
+                .into_temp_path();
+            let trips = mk_trips();
+            let add_rollup = |ts: &mut Series<BikeTrip>| {
+                ts.add_rollup(
+                    "monthly",
+                    &rollup_path,
+                    Bucket::Month,
+                    BucketZone::Utc,
+                    |trip: &BikeTrip| trip.duration.0.value_unsafe,
+                )
+                .expect("rollup should be added");
+            };
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                add_rollup(&mut ts);
+                let ids: Vec<UniqueId> = trips
+                    .iter()
+                    .map(|trip| ts.put(trip.clone()).expect("expect a successful put"))
+                    .collect();
+                ts.remove_rollup("monthly");
+
+                // Without a retention policy, the October records were deleted, not expired.
+                ts.delete(&ids[0]).expect("expect a successful delete");
+                ts.delete(&ids[1]).expect("expect a successful delete");
+                add_rollup(&mut ts);
+                assert_eq!(
+                    months(ts.rollup("monthly").unwrap()),
+                    vec![(11, 3, 13560.0)]
+                );
+
+                ts.put(trips[0].clone()).expect("expect a successful put");
+                ts.put(trips[1].clone()).expect("expect a successful put");
+                assert_eq!(
+                    months(ts.rollup("monthly").unwrap()),
+                    vec![(10, 2, 13920.0), (11, 3, 13560.0)]
+                );
+            }
+
+            let mut ts: Series<BikeTrip> = SeriesOptions::new()
+                .retention(RetentionPolicy {
+                    max_records: Some(3),
+                    ..RetentionPolicy::default()
+                })
+                .open(&path)
+                .expect("expect the time series to open correctly");
+            add_rollup(&mut ts);
+            assert_eq!(
+                months(ts.rollup("monthly").unwrap()),
+                vec![(10, 2, 13920.0), (11, 3, 13560.0)]
+            );
+
+            drop(ts);
+            fs::write(&path, "").expect("expect the series to be emptied");
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            add_rollup(&mut ts);
+            assert!(months(ts.rollup("monthly").unwrap()).is_empty());
+        })
+    }
+
+    #[test]
+    pub fn retention_drops_the_oldest_records_on_open() {
+        run_test(|path| {
+            let trips = mk_trips();
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                for trip in &trips {
+                    ts.put(trip.clone()).expect("expect a successful put");
+                }
+            }
+
+            let ts: Series<BikeTrip> = SeriesOptions::new()
+                .retention(RetentionPolicy {
+                    max_records: Some(2),
+                    ..RetentionPolicy::default()
+                })
+                .open(&path)
+                .expect("expect the time series to open correctly");
+            let kept: Vec<BikeTrip> = ts.records().unwrap().map(|r| r.data.clone()).collect();
+            assert_eq!(kept, trips[3..].to_vec());
+            assert_eq!(count_lines(&path), 2);
+        })
+    }
+
+    #[test]
+    pub fn retention_keeps_everything_younger_than_a_huge_age() {
+        run_test(|path| {
+            let trips = mk_trips();
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            for trip in &trips {
+                ts.put(trip.clone()).expect("expect a successful put");
+            }
+
+            ts.set_retention_policy(RetentionPolicy {
+                max_age: Some(chrono::Duration::days(1_000_000_000)),
+                ..RetentionPolicy::default()
+            })
+            .expect("retention should be enforced");
+            ts.compact().expect("compaction should succeed");
+            assert_eq!(ts.all_records().unwrap().len(), trips.len());
+        })
+    }
+
+    #[test]
+    pub fn retention_expires_old_records_on_compaction() {
+        run_test(|path| {
+            let rollup_path = tempfile::NamedTempFile::new()
+                .expect("temporary path created")
+                .into_temp_path();
+            let trips = mk_trips();
+            let mut recent = trips[0].clone();
+            recent.datetime =
+                DateTimeTz((Utc::now() - chrono::Duration::days(1)).with_timezone(&UTC));
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            ts.add_rollup(
+                "yearly",
+                &rollup_path,
+                Bucket::Year,
+                BucketZone::Utc,
+                |trip: &BikeTrip| trip.duration.0.value_unsafe,
+            )
+            .expect("rollup should be added");
+            ts.put(trips[0].clone()).expect("expect a successful put");
+            ts.put(recent.clone()).expect("expect a successful put");
+            ts.set_retention_policy(RetentionPolicy {
+                max_age: Some(chrono::Duration::days(30)),
+                ..RetentionPolicy::default()
+            })
+            .expect("retention should be enforced");
+            assert_eq!(ts.all_records().unwrap().len(), 1);
+            assert_eq!(count_lines(&path), 1);
+
+            // The rollup still summarizes the expired record.
+            let years = ts.rollup("yearly").unwrap().all_records().unwrap();
+            assert_eq!(years.len(), 2);
+            assert_eq!(years[0].data.sum, 11040.0);
+
+            ts.put(trips[1].clone()).expect("expect a successful put");
+            assert_eq!(ts.all_records().unwrap().len(), 2);
+            ts.compact().expect("compaction should succeed");
+            let kept: Vec<BikeTrip> = ts.records().unwrap().map(|r| r.data.clone()).collect();
+            assert_eq!(kept, vec![recent]);
+            // The expired record had the latest revision, which is kept on a line of its own.
+            assert_eq!(count_lines(&path), 2);
+        })
+    }
+
+    fn count_lines(path: &tempfile::TempPath) -> usize {
+        std::fs::read_to_string(path)
+            .expect("series file should be readable")
+            .lines()
+            .count()
+    }
+
+    #[test]
+    pub fn compaction_keeps_one_line_per_record() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+                ts.put(trips[1].clone()).expect("expect a successful put");
+                let deleted_id = ts.put(trips[2].clone()).expect("expect a successful put");
+
+                let mut trip = ts.get(&trip_id).unwrap().unwrap();
+                trip.data.distance = Distance(50000.0 * M);
+                ts.update(trip).expect("expect record to update");
+                ts.delete(&deleted_id).expect("successful delete");
+                assert_eq!(ts.superseded_lines(), 3);
+
+                ts.compact().expect("compaction should succeed");
+                assert_eq!(ts.superseded_lines(), 0);
+                // One line for each of the two live records, after one that keeps the revision of
+                // the deletion.
+                assert_eq!(count_lines(&path), 3);
+
+                ts.put(trips[3].clone()).expect("expect a successful put");
+                assert_eq!(count_lines(&path), 4);
+            }
+
+            {
+                let ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                let recs = ts.all_records().expect("good record retrieval");
+                assert_eq!(recs.len(), 3);
+                match ts.get(&trip_id_for(&ts, &trips[0])) {
+                    Ok(Some(trip)) => assert_eq!(trip.data.distance, Distance(50000.0 * M)),
+                    _ => panic!("updated record should survive compaction"),
+                }
+            }
+        })
+    }
+
+    #[test]
+    pub fn revisions_keep_increasing_across_compaction() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                ts.put(trips[0].clone()).expect("expect a successful put");
+                let deleted_id = ts.put(trips[1].clone()).expect("expect a successful put");
+                ts.delete(&deleted_id).expect("successful delete");
+                ts.compact().expect("compaction should succeed");
+            }
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            assert_eq!(ts.all_records().unwrap().len(), 1);
+            let trip_id = ts.put(trips[2].clone()).expect("expect a successful put");
+            assert_eq!(ts.get(&trip_id).unwrap().unwrap().revision(), 4);
+            assert_eq!(ts.history(&trip_id).unwrap()[0].revision, 4);
+        })
+    }
+
+    fn trip_id_for(ts: &Series<BikeTrip>, trip: &BikeTrip) -> UniqueId {
+        ts.search(exact_time(trip.datetime.clone()))
+            .expect("search should succeed")
+            .pop()
+            .expect("the trip should be present")
+            .id
+    }
+
+    #[test]
+    pub fn compacts_automatically_past_threshold() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            ts.set_compaction_threshold(Some(2))
+                .expect("setting the threshold should succeed");
+
+            let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+            for _ in 0..2 {
+                let trip = ts.get(&trip_id).unwrap().unwrap();
+                ts.update(trip).expect("expect record to update");
+            }
+            assert_eq!(count_lines(&path), 3);
+
+            let trip = ts.get(&trip_id).unwrap().unwrap();
+            ts.update(trip).expect("expect record to update");
+            assert_eq!(count_lines(&path), 1);
+            assert_eq!(ts.superseded_lines(), 0);
+        })
+    }
+
+    #[test]
+    pub fn writes_survive_a_failed_automatic_compaction() {
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+            ts.set_compaction_threshold(Some(0))
+                .expect("setting the threshold should succeed");
+
+            // A directory in the way of the temporary file makes every compaction fail.
+            let blocker = compaction_path(&path);
+            fs::create_dir(&blocker).expect("the blocking directory should be created");
+            let trip = ts.get(&trip_id).unwrap().unwrap();
+            ts.update(trip)
+                .expect("the update should succeed without compaction");
+            assert_eq!(count_lines(&path), 2);
+            assert!(ts.compact().is_err());
+
+            fs::remove_dir(&blocker).expect("the blocking directory should be removed");
+            ts.put(trips[1].clone()).expect("expect a successful put");
+            assert_eq!(count_lines(&path), 2);
+            assert_eq!(ts.all_records().unwrap().len(), 2);
+        })
+    }
+
+    fn append_to_file(path: &tempfile::TempPath, contents: &str) {
+        let mut f = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .expect("series file should open for appending");
+        f.write_all(contents.as_bytes())
+            .expect("appending to the series file should succeed");
     }
 
     #[test]
-    pub fn can_add_and_retrieve_entries() {
+    pub fn truncates_a_torn_final_line() {
         run_test(|path| {
             let trips = mk_trips();
-            let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                .expect("expect the time series to open correctly");
-            let uuid = ts.put(trips[0].clone()).expect("expect a successful put");
-            let record_res = ts.get(&uuid);
 
-            for trip in &trips[1..=4] {
-                ts.put(trip.clone()).expect("expect a successful put");
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                for trip in &trips[0..=2] {
+                    ts.put(trip.clone()).expect("expect a successful put");
+                }
             }
+            let intact_len = std::fs::metadata(&path).unwrap().len();
+            append_to_file(
+                &path,
+                "{\"id\":\"3330c5b0-783f-4919-b2c4-8169c38f65ff\",\"da",
+            );
 
-            match record_res {
-                Err(err) => assert!(false, err),
-                Ok(None) => assert!(false, "There should have been a value here"),
-                Ok(Some(tr)) => {
-                    assert_eq!(tr.id, uuid);
-                    assert_eq!(
-                        tr.timestamp(),
-                        DateTimeTz(UTC.ymd(2011, 10, 29).and_hms(0, 0, 0))
-                    );
-                    assert_eq!(tr.data.duration, Duration(11040.0 * S));
-                    assert_eq!(tr.data.comments, String::from("long time ago"));
-                    assert_eq!(tr.data, trips[0]);
-                }
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("a torn final line should not prevent opening");
+                assert_eq!(ts.all_records().unwrap().len(), 3);
+                assert_eq!(
+                    ts.torn_line(),
+                    Some(&TornLine {
+                        offset: intact_len,
+                        contents: String::from(
+                            "{\"id\":\"3330c5b0-783f-4919-b2c4-8169c38f65ff\",\"da"
+                        ),
+                    })
+                );
+                assert_eq!(std::fs::metadata(&path).unwrap().len(), intact_len);
+                ts.put(trips[3].clone()).expect("expect a successful put");
+            }
+
+            {
+                let ts: Series<BikeTrip> = SeriesOptions::new()
+                    .recovery(Recovery::Strict)
+                    .open(&path)
+                    .expect("the repaired file should open strictly");
+                assert_eq!(ts.all_records().unwrap().len(), 4);
+                assert_eq!(ts.torn_line(), None);
             }
         })
     }
 
     #[test]
-    pub fn can_retrieve_entries_iterator() {
+    pub fn strict_recovery_refuses_a_torn_final_line() {
         run_test(|path| {
             let trips = mk_trips();
-            let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                .expect("expect the time series to open correctly");
 
-            for trip in &trips[0..=4] {
-                ts.put(trip.clone()).expect("expect a successful put");
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                ts.put(trips[0].clone()).expect("expect a successful put");
             }
+            append_to_file(&path, "{\"id\":");
+            let torn_len = std::fs::metadata(&path).unwrap().len();
 
-            let as_vec = ts.all_records().expect("retrieval is currently infallible");
-            let as_iter = ts.records().expect("retrieval is currently infallible");
-
-            for (from_vec, from_iter) in as_vec.iter().zip(as_iter) {
-                assert_eq!(from_iter.id, from_vec.id);
-                assert_eq!(from_iter.data, from_vec.data);
+            match SeriesOptions::new()
+                .recovery(Recovery::Strict)
+                .open::<BikeTrip, _>(&path)
+            {
+                Err(Error::JSONParseError(_)) => (),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("strict recovery should not open a torn file"),
             }
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), torn_len);
         })
     }
 
     #[test]
-    pub fn can_search_for_an_entry_with_exact_time() {
+    pub fn terminates_a_complete_final_line() {
         run_test(|path| {
             let trips = mk_trips();
-            let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                .expect("expect the time series to open correctly");
 
-            for trip in &trips[0..=4] {
-                ts.put(trip.clone()).expect("expect a successful put");
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                ts.put(trips[0].clone()).expect("expect a successful put");
             }
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::write(&path, contents.trim_end()).unwrap();
 
-            match ts.search(exact_time(DateTimeTz(
-                UTC.ymd(2011, 10, 31).and_hms(0, 0, 0),
-            ))) {
-                Err(err) => assert!(false, err),
-                Ok(v) => {
-                    assert_eq!(v.len(), 1);
-                    assert_eq!(v[0].data, trips[1]);
-                }
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                assert_eq!(ts.torn_line(), None);
+                ts.put(trips[1].clone()).expect("expect a successful put");
             }
+
+            let ts: Series<BikeTrip> = SeriesOptions::new()
+                .recovery(Recovery::Strict)
+                .open(&path)
+                .expect("expect the time series to open correctly");
+            assert_eq!(ts.all_records().unwrap().len(), 2);
         })
     }
 
     #[test]
-    pub fn can_get_entries_in_time_range() {
+    pub fn syncs_according_to_policy() {
         run_test(|path| {
             let trips = mk_trips();
-            let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                .expect("expect the time series to open correctly");
 
-            for trip in &trips[0..=4] {
-                ts.put(trip.clone()).expect("expect a successful put");
-            }
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            ts.put(trips[0].clone()).expect("expect a successful put");
+            assert_eq!(ts.unsynced_writes, 1);
 
-            match ts.search_sorted(
-                time_range(
-                    DateTimeTz(UTC.ymd(2011, 10, 31).and_hms(0, 0, 0)),
-                    true,
-                    DateTimeTz(UTC.ymd(2011, 11, 04).and_hms(0, 0, 0)),
-                    true,
-                ),
-                |l, r| l.timestamp().cmp(&r.timestamp()),
-            ) {
-                Err(err) => assert!(false, err),
-                Ok(v) => {
-                    assert_eq!(v.len(), 3);
-                    assert_eq!(v[0].data, trips[1]);
-                    assert_eq!(v[1].data, trips[2]);
-                    assert_eq!(v[2].data, trips[3]);
-                }
-            }
+            ts.set_sync_policy(SyncPolicy::EveryNWrites(3))
+                .expect("expect the policy to be set");
+            ts.put(trips[1].clone()).expect("expect a successful put");
+            assert_eq!(ts.unsynced_writes, 2);
+            ts.put(trips[2].clone()).expect("expect a successful put");
+            assert_eq!(ts.unsynced_writes, 0);
+
+            ts.put(trips[3].clone()).expect("expect a successful put");
+            ts.sync().expect("expect a successful sync");
+            assert_eq!(ts.unsynced_writes, 0);
+
+            ts.set_sync_policy(SyncPolicy::Always)
+                .expect("expect the policy to be set");
+            ts.put(trips[4].clone()).expect("expect a successful put");
+            assert_eq!(ts.unsynced_writes, 0);
         })
     }
 
+    fn wait_for_sync(ts: &Series<BikeTrip>) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while ts.syncer.as_ref().unwrap().is_dirty() {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "the write was never synced"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+    }
+
     #[test]
-    pub fn persists_and_reads_an_entry() {
+    pub fn syncs_on_an_interval_without_further_writes() {
         run_test(|path| {
             let trips = mk_trips();
 
-            {
-                let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            ts.set_sync_policy(SyncPolicy::Interval(std::time::Duration::from_millis(20)))
+                .expect("expect the policy to be set");
+            ts.put(trips[0].clone()).expect("expect a successful put");
+            ts.put(trips[1].clone()).expect("expect a successful put");
+            assert!(ts.syncer.as_ref().unwrap().is_dirty());
 
-                for trip in &trips[0..=4] {
-                    ts.put(trip.clone()).expect("expect a successful put");
-                }
-            }
+            // Nothing else is written, and still the burst gets synced.
+            wait_for_sync(&ts);
 
-            {
-                let ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
-                match ts.search_sorted(
-                    time_range(
-                        DateTimeTz(UTC.ymd(2011, 10, 31).and_hms(0, 0, 0)),
-                        true,
-                        DateTimeTz(UTC.ymd(2011, 11, 04).and_hms(0, 0, 0)),
-                        true,
-                    ),
-                    |l, r| l.timestamp().cmp(&r.timestamp()),
-                ) {
-                    Err(err) => assert!(false, err),
-                    Ok(v) => {
-                        assert_eq!(v.len(), 3);
-                        assert_eq!(v[0].data, trips[1]);
-                        assert_eq!(v[1].data, trips[2]);
-                        assert_eq!(v[2].data, trips[3]);
-                    }
-                }
-            }
+            ts.compact().expect("expect a successful compaction");
+            ts.put(trips[2].clone()).expect("expect a successful put");
+            wait_for_sync(&ts);
+
+            ts.set_sync_policy(SyncPolicy::Never)
+                .expect("expect the policy to be set");
+            assert!(ts.syncer.is_none());
         })
     }
 
     #[test]
-    pub fn can_write_to_existing_file() {
+    pub fn refuses_a_second_writer() {
         run_test(|path| {
             let trips = mk_trips();
 
-            {
-                let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            ts.put(trips[0].clone()).expect("expect a successful put");
 
-                for trip in &trips[0..=2] {
-                    ts.put(trip.clone()).expect("expect a successful put");
-                }
+            match Series::<BikeTrip>::open(&path) {
+                Err(Error::FileLocked) => (),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("a second writer should not be able to open the series"),
             }
 
-            {
-                let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
-                match ts.search_sorted(
-                    time_range(
-                        DateTimeTz(UTC.ymd(2011, 10, 31).and_hms(0, 0, 0)),
-                        true,
-                        DateTimeTz(UTC.ymd(2011, 11, 04).and_hms(0, 0, 0)),
-                        true,
-                    ),
-                    |l, r| l.timestamp().cmp(&r.timestamp()),
-                ) {
-                    Err(err) => assert!(false, err),
-                    Ok(v) => {
-                        assert_eq!(v.len(), 2);
-                        assert_eq!(v[0].data, trips[1]);
-                        assert_eq!(v[1].data, trips[2]);
-                        ts.put(trips[3].clone()).expect("expect a successful put");
-                        ts.put(trips[4].clone()).expect("expect a successful put");
-                    }
-                }
+            ts.compact().expect("compaction should succeed");
+            match Series::<BikeTrip>::open(&path) {
+                Err(Error::FileLocked) => (),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("the compacted file should still be locked"),
             }
 
-            {
-                let ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
-                match ts.search_sorted(
-                    time_range(
-                        DateTimeTz(UTC.ymd(2011, 10, 31).and_hms(0, 0, 0)),
-                        true,
-                        DateTimeTz(UTC.ymd(2011, 11, 05).and_hms(0, 0, 0)),
-                        true,
-                    ),
-                    |l, r| l.timestamp().cmp(&r.timestamp()),
-                ) {
-                    Err(err) => assert!(false, err),
-                    Ok(v) => {
-                        assert_eq!(v.len(), 4);
-                        assert_eq!(v[0].data, trips[1]);
-                        assert_eq!(v[1].data, trips[2]);
-                        assert_eq!(v[2].data, trips[3]);
-                        assert_eq!(v[3].data, trips[4]);
-                    }
-                }
-            }
+            drop(ts);
+            Series::<BikeTrip>::open(&path)
+                .expect("the series should open once the first writer is gone");
         })
     }
 
+    #[cfg(unix)]
     #[test]
-    pub fn can_overwrite_existing_entry() {
+    pub fn locks_the_file_that_replaced_the_one_it_opened() {
         run_test(|path| {
-            let trips = mk_trips();
+            let open = || OpenOptions::new().read(true).append(true).open(&path);
+            let orphan = open().expect("expect the file to open");
+
+            // Another process compacts between the open and the lock.
+            let replacement = compaction_path(&path);
+            fs::write(&replacement, "").expect("expect the replacement to be written");
+            fs::rename(&replacement, &path).expect("expect the replacement to be renamed");
+
+            let mut f = lock_current(orphan, &path, LockMode::Exclusive, open)
+                .expect("expect the file to be locked");
+            let current = File::open(&path).expect("expect the file to open");
+            assert!(!is_replaced(&current, &f).unwrap());
+
+            f.write_all(b"written\n")
+                .expect("expect a successful write");
+            assert_eq!(count_lines(&path), 1);
+        })
+    }
 
-            let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                .expect("expect the time series to open correctly");
+    #[test]
+    pub fn read_only_open_does_not_create_the_file() {
+        let dir = tempfile::tempdir().expect("temporary directory created");
+        let path = dir.path().join("missing.json");
+
+        match Series::<BikeTrip, ReadOnly>::open_read_only(&path) {
+            Err(Error::IOError(_)) => (),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("a missing file should not open read-only"),
+        }
+        assert!(!path.exists());
+    }
 
-            ts.put(trips[0].clone()).expect("expect a successful put");
-            ts.put(trips[1].clone()).expect("expect a successful put");
-            let trip_id = ts.put(trips[2].clone()).expect("expect a successful put");
+    #[test]
+    pub fn readers_share_the_file_but_exclude_writers() {
+        run_test(|path| {
+            let trips = mk_trips();
 
-            match ts.get(&trip_id) {
-                Err(err) => assert!(false, err),
-                Ok(None) => assert!(false, "record not found"),
-                Ok(Some(mut trip)) => {
-                    trip.data.distance = Distance(50000.0 * M);
-                    ts.update(trip).expect("expect record to update");
+            {
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                for trip in &trips[0..=2] {
+                    ts.put(trip.clone()).expect("expect a successful put");
                 }
-            };
 
-            match ts.get(&trip_id) {
-                Err(err) => assert!(false, err),
-                Ok(None) => assert!(false, "record not found"),
-                Ok(Some(trip)) => {
-                    assert_eq!(
-                        trip.data.datetime,
-                        DateTimeTz(UTC.ymd(2011, 11, 02).and_hms(0, 0, 0))
-                    );
-                    assert_eq!(trip.data.distance, Distance(50000.0 * M));
-                    assert_eq!(trip.data.duration, Duration(7020.0 * S));
-                    assert_eq!(trip.data.comments, String::from("Do Some Distance!"));
+                match Series::<BikeTrip, ReadOnly>::open_read_only(&path) {
+                    Err(Error::FileLocked) => (),
+                    Err(err) => panic!("unexpected error: {}", err),
+                    Ok(_) => panic!("a reader should not open a series with a writer"),
                 }
             }
+
+            let reader: Series<BikeTrip, ReadOnly> =
+                Series::open_read_only(&path).expect("expect the time series to open read-only");
+            let second_reader: Series<BikeTrip, ReadOnly> =
+                Series::open_read_only(&path).expect("a second reader should open as well");
+            assert_eq!(reader.all_records().unwrap().len(), 3);
+            assert_eq!(second_reader.all_records().unwrap().len(), 3);
+
+            match Series::<BikeTrip>::open(&path) {
+                Err(Error::FileLocked) => (),
+                Err(err) => panic!("unexpected error: {}", err),
+                Ok(_) => panic!("a writer should not open a series with readers"),
+            }
         })
     }
 
+    fn open_follower(path: &tempfile::TempPath) -> Series<BikeTrip, ReadOnly> {
+        SeriesOptions::new()
+            .read_only()
+            .lock_mode(LockMode::Unlocked)
+            .open(path)
+            .expect("expect the time series to open read-only")
+    }
+
     #[test]
-    pub fn record_overwrites_get_persisted() {
+    pub fn refresh_applies_lines_from_another_writer() {
+        use subscription::Event;
+
         run_test(|path| {
             let trips = mk_trips();
 
-            {
-                let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
+            let mut writer: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let first_id = writer
+                .put(trips[0].clone())
+                .expect("expect a successful put");
+
+            let mut reader = open_follower(&path);
+            let (_, events) = reader.subscribe_channel(Interval::unbounded());
+            assert_eq!(reader.all_records().unwrap().len(), 1);
+            assert_eq!(reader.refresh().unwrap(), 0);
+
+            writer
+                .put(trips[1].clone())
+                .expect("expect a successful put");
+            let mut batch = writer.batch();
+            batch.put(trips[2].clone());
+            batch.delete(&first_id);
+            batch.commit().expect("the batch should commit");
+            assert_eq!(reader.refresh().unwrap(), 3);
+            assert_eq!(reader.all_records().unwrap().len(), 2);
+            assert!(reader.get(&first_id).unwrap().is_none());
+            assert_eq!(reader.superseded_lines(), 2);
+            let kinds: Vec<&str> = events
+                .try_iter()
+                .map(|event| match event {
+                    Event::Put(_) => "put",
+                    Event::Update { .. } => "update",
+                    Event::Delete(_) => "delete",
+                })
+                .collect();
+            assert_eq!(kinds, vec!["put", "put", "delete"]);
+
+            // A line that is still being written waits for the next refresh.
+            append_to_file(
+                &path,
+                &format!(
+                    "{{\"id\":\"3330c5b0-783f-4919-b2c4-8169c38f65ff\",\"data\":{}}}",
+                    serde_json::to_string(&trips[3]).unwrap()
+                ),
+            );
+            assert_eq!(reader.refresh().unwrap(), 0);
+            append_to_file(&path, "\n");
+            assert_eq!(reader.refresh().unwrap(), 1);
+            assert_eq!(reader.all_records().unwrap().len(), 3);
+
+            // A corrupt line is reported by every refresh, rather than skipped after the first.
+            append_to_file(&path, "not a record\n");
+            assert!(reader.refresh().is_err());
+            assert!(reader.refresh().is_err());
+            assert_eq!(reader.all_records().unwrap().len(), 3);
+        })
+    }
 
-                ts.put(trips[0].clone()).expect("expect a successful put");
-                ts.put(trips[1].clone()).expect("expect a successful put");
-                let trip_id = ts.put(trips[2].clone()).expect("expect a successful put");
+    #[test]
+    pub fn refresh_reloads_a_compacted_file() {
+        use subscription::Event;
 
-                match ts.get(&trip_id) {
-                    Err(err) => assert!(false, err),
-                    Ok(None) => assert!(false, "record not found"),
-                    Ok(Some(mut trip)) => {
-                        trip.data.distance = Distance(50000.0 * M);
-                        ts.update(trip).expect("expect record to update");
-                    }
-                };
+        run_test(|path| {
+            let trips = mk_trips();
+
+            let mut writer: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let first_id = writer
+                .put(trips[0].clone())
+                .expect("expect a successful put");
+            let second_id = writer
+                .put(trips[1].clone())
+                .expect("expect a successful put");
+
+            let mut reader = open_follower(&path);
+            let (_, events) = reader.subscribe_channel(Interval::unbounded());
+
+            writer.delete(&first_id).expect("successful delete");
+            let mut second = writer.get(&second_id).unwrap().unwrap();
+            second.data.comments = String::from("revised");
+            writer.update(second).expect("expect record to update");
+            writer.compact().expect("compaction should succeed");
+            writer
+                .put(trips[2].clone())
+                .expect("expect a successful put");
+
+            assert_eq!(reader.refresh().unwrap(), 3);
+            assert_eq!(reader.all_records().unwrap().len(), 2);
+            assert_eq!(
+                reader.get(&second_id).unwrap().unwrap().data.comments,
+                "revised"
+            );
+            assert_eq!(reader.superseded_lines(), 0);
+            assert_eq!(events.try_iter().count(), 3);
+
+            writer
+                .put(trips[3].clone())
+                .expect("expect a successful put");
+            assert_eq!(reader.refresh().unwrap(), 1);
+            match events.try_recv() {
+                Ok(Event::Put(record)) => assert_eq!(record.data, trips[3]),
+                other => panic!("expected a put, got {:?}", other),
             }
+        })
+    }
 
-            {
-                let ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
+    #[test]
+    pub fn watches_the_file_for_changes() {
+        use shared::SharedSeries;
+        use subscription::Event;
 
-                match ts.all_records() {
-                    Err(err) => assert!(false, err),
-                    Ok(trips) => assert_eq!(trips.len(), 3),
-                }
+        run_test(|path| {
+            let trips = mk_trips();
 
-                match ts.search(exact_time(DateTimeTz(
-                    UTC.ymd(2011, 11, 02).and_hms(0, 0, 0),
-                ))) {
-                    Err(err) => assert!(false, err),
-                    Ok(trips) => {
-                        assert_eq!(trips.len(), 1);
-                        assert_eq!(
-                            trips[0].data.datetime,
-                            DateTimeTz(UTC.ymd(2011, 11, 02).and_hms(0, 0, 0))
-                        );
-                        assert_eq!(trips[0].data.distance, Distance(50000.0 * M));
-                        assert_eq!(trips[0].data.duration, Duration(7020.0 * S));
-                        assert_eq!(trips[0].data.comments, String::from("Do Some Distance!"));
-                    }
-                }
+            let mut writer: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let mut reader = open_follower(&path);
+            let (_, events) = reader.subscribe_channel(Interval::unbounded());
+            let shared = SharedSeries::new(reader);
+            let watcher = shared.watch(std::time::Duration::from_millis(5));
+
+            let id = writer
+                .put(trips[0].clone())
+                .expect("expect a successful put");
+            match events.recv_timeout(std::time::Duration::from_secs(5)) {
+                Ok(Event::Put(record)) => assert_eq!(record.id, id),
+                other => panic!("expected a put, got {:?}", other),
             }
+            assert_eq!(shared.read().all_records().len(), 1);
+            watcher.stop().expect("the watcher should stop cleanly");
         })
     }
 
     #[test]
-    pub fn can_delete_an_entry() {
+    pub fn read_only_open_leaves_a_torn_line_in_place() {
         run_test(|path| {
             let trips = mk_trips();
 
             {
-                let mut ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
-                let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
-                ts.put(trips[1].clone()).expect("expect a successful put");
-                ts.put(trips[2].clone()).expect("expect a successful put");
+                let mut ts: Series<BikeTrip> =
+                    Series::open(&path).expect("expect the time series to open correctly");
+                ts.put(trips[0].clone()).expect("expect a successful put");
+            }
+            append_to_file(&path, "{\"id\":");
+            let torn_len = std::fs::metadata(&path).unwrap().len();
+
+            let ts: Series<BikeTrip, ReadOnly> =
+                Series::open_read_only(&path).expect("expect the time series to open read-only");
+            assert_eq!(ts.all_records().unwrap().len(), 1);
+            assert!(ts.torn_line().is_some());
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), torn_len);
+        })
+    }
 
-                ts.delete(&trip_id).expect("successful delete");
+    #[test]
+    pub fn options_can_refuse_to_create_the_file() {
+        let dir = tempfile::tempdir().expect("temporary directory created");
+        let path = dir.path().join("missing.json");
+
+        match SeriesOptions::new()
+            .create(false)
+            .open::<BikeTrip, _>(&path)
+        {
+            Err(Error::IOError(_)) => (),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("a missing file should not be created"),
+        }
+        assert!(!path.exists());
+    }
 
-                let recs = ts.all_records().expect("good record retrieval");
-                assert_eq!(recs.len(), 2);
-            }
+    #[test]
+    pub fn options_apply_on_open() {
+        run_test(|path| {
+            let trips = mk_trips();
 
-            {
-                let ts: Series<BikeTrip> = Series::open(&path.to_string_lossy())
-                    .expect("expect the time series to open correctly");
-                let recs = ts.all_records().expect("good record retrieval");
-                assert_eq!(recs.len(), 2);
+            let mut ts: Series<BikeTrip> =
+                Series::open(&path).expect("expect the time series to open correctly");
+            let trip_id = ts.put(trips[0].clone()).expect("expect a successful put");
+            for _ in 0..3 {
+                let trip = ts.get(&trip_id).unwrap().unwrap();
+                ts.update(trip).expect("expect record to update");
             }
+
+            let second: Series<BikeTrip, ReadOnly> = SeriesOptions::new()
+                .read_only()
+                .lock_mode(LockMode::Unlocked)
+                .open(&path)
+                .expect("an unlocked reader should open alongside the writer");
+            assert_eq!(second.superseded_lines(), 3);
+            drop(second);
+            drop(ts);
+
+            let ts: Series<BikeTrip> = SeriesOptions::new()
+                .compaction_threshold(Some(2))
+                .sync_policy(SyncPolicy::Always)
+                .open(&path)
+                .expect("expect the time series to open correctly");
+            assert_eq!(ts.superseded_lines(), 0);
+            assert_eq!(ts.sync_policy, SyncPolicy::Always);
+            assert_eq!(count_lines(&path), 1);
         })
     }
 
@@ -607,9 +3593,14 @@ mod tests {
             .expect("something is wrong with this ID");
         let rec = ts.get(&uid);
         match rec {
-            Err(err) => assert!(false, err),
-            Ok(None) => assert!(false, "no record found"),
-            Ok(Some(rec)) => assert_eq!(rec.data.weight, Weight(77.79109 * KG)),
+            Err(err) => panic!("{}", err),
+            Ok(None) => panic!("no record found"),
+            Ok(Some(rec)) => {
+                assert_eq!(rec.data.weight, Weight(77.79109 * KG));
+                assert_eq!(rec.created_at(), None);
+                assert_eq!(rec.updated_at(), None);
+                assert_eq!(rec.revision(), 0);
+            }
         }
     }
 }
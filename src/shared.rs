@@ -0,0 +1,228 @@
+extern crate serde;
+
+use self::serde::de::DeserializeOwned;
+use self::serde::ser::Serialize;
+use std::ops::{Deref, DerefMut};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use history::Snapshot;
+use series::{Access, ReadWrite, Series};
+use types::{Error, Recordable};
+
+/// A handle to a series that can be cloned and shared between threads.
+///
+/// One thread at a time may write to the series. Every other thread reads a snapshot of the
+/// records as they were after the last write finished, so reads never wait for a write or for
+/// the file I/O that goes with it. A write is published to the readers once its guard is dropped.
+///
+/// ```text
+/// let shared = SharedSeries::new(Series::open("var/bike_trips.json")?);
+///
+/// let writer = shared.clone();
+/// thread::spawn(move || writer.write().put(trip));
+///
+/// let trips = shared.read().search(time_range(start, true, end, false));
+/// ```
+pub struct SharedSeries<T: Clone + Recordable + DeserializeOwned + Serialize, A: Access = ReadWrite>
+{
+    series: Arc<Mutex<Series<T, A>>>,
+
+    /// The records as of the last write, which is all that readers ever lock, and only long
+    /// enough to clone it.
+    published: Arc<RwLock<Snapshot<T>>>,
+}
+
+impl<T, A> SharedSeries<T, A>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    /// Share an open series.
+    pub fn new(series: Series<T, A>) -> SharedSeries<T, A> {
+        let published = series.snapshot();
+        SharedSeries {
+            series: Arc::new(Mutex::new(series)),
+            published: Arc::new(RwLock::new(published)),
+        }
+    }
+
+    /// The records as they were when the last write finished. This never waits for a write in
+    /// progress, and the snapshot stays as it is whatever is written afterwards.
+    pub fn read(&self) -> Snapshot<T> {
+        match self.published.read() {
+            Ok(published) => published.clone(),
+            // Nothing can panic while the snapshot is being replaced, so it is always whole.
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn lock_exclusive(&self) -> WriteGuard<'_, T, A> {
+        WriteGuard {
+            series: self
+                .series
+                .lock()
+                .expect("a thread panicked while writing to the series"),
+            published: &self.published,
+        }
+    }
+
+    /// Apply the lines that another process appended to the file, as with `Series::refresh`.
+    pub fn refresh(&self) -> Result<usize, Error> {
+        self.lock_exclusive().refresh()
+    }
+
+    /// Refresh the series from its file every `interval` on a thread of its own, until the
+    /// returned `Watcher` is stopped or dropped. Subscribe to the series to hear about the
+    /// changes.
+    pub fn watch(&self, interval: Duration) -> Watcher
+    where
+        T: 'static,
+        A: 'static,
+        Series<T, A>: Send,
+        Snapshot<T>: Send + Sync,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let series = self.clone();
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                series.refresh()?;
+                thread::park_timeout(interval);
+            }
+            Ok(())
+        });
+        Watcher {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<T> SharedSeries<T, ReadWrite>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+{
+    /// Lock the series for writing, waiting until no other thread is writing to it. Readers go on
+    /// reading the records as they were, and see every write made through the guard once it is
+    /// dropped.
+    ///
+    /// Panics if a thread panicked while it was writing to the series, since the series may have
+    /// been left half-updated.
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        self.lock_exclusive()
+    }
+}
+
+/// Exclusive access to the series behind a `SharedSeries`, as returned by `SharedSeries::write`.
+/// Dropping the guard publishes the records to the readers.
+pub struct WriteGuard<'a, T, A = ReadWrite>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    series: MutexGuard<'a, Series<T, A>>,
+    published: &'a RwLock<Snapshot<T>>,
+}
+
+impl<'a, T, A> Deref for WriteGuard<'a, T, A>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    type Target = Series<T, A>;
+
+    fn deref(&self) -> &Series<T, A> {
+        &self.series
+    }
+}
+
+impl<'a, T, A> DerefMut for WriteGuard<'a, T, A>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    fn deref_mut(&mut self) -> &mut Series<T, A> {
+        &mut self.series
+    }
+}
+
+impl<'a, T, A> Drop for WriteGuard<'a, T, A>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    fn drop(&mut self) {
+        // A write that panicked may have left the records half-updated, so readers keep the last
+        // snapshot that was whole.
+        if thread::panicking() {
+            return;
+        }
+        let snapshot = self.series.snapshot();
+        match self.published.write() {
+            Ok(mut published) => *published = snapshot,
+            Err(poisoned) => *poisoned.into_inner() = snapshot,
+        }
+    }
+}
+
+impl<T, A> Clone for SharedSeries<T, A>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    fn clone(&self) -> SharedSeries<T, A> {
+        SharedSeries {
+            series: Arc::clone(&self.series),
+            published: Arc::clone(&self.published),
+        }
+    }
+}
+
+impl<T, A> From<Series<T, A>> for SharedSeries<T, A>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+    A: Access,
+{
+    fn from(series: Series<T, A>) -> SharedSeries<T, A> {
+        SharedSeries::new(series)
+    }
+}
+
+/// A thread that refreshes a `SharedSeries` from its file, as started by `SharedSeries::watch`.
+/// Dropping the watcher stops the thread.
+pub struct Watcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<Result<(), Error>>>,
+}
+
+impl Watcher {
+    /// Stop refreshing the series, and wait for the thread to finish. If a refresh failed, the
+    /// thread stopped then, and this returns the error.
+    pub fn stop(mut self) -> Result<(), Error> {
+        match self.halt() {
+            Ok(result) => result,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+
+    fn halt(&mut self) -> thread::Result<Result<(), Error>> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => {
+                thread.thread().unpark();
+                thread.join()
+            }
+            None => Ok(Ok(())),
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = self.halt();
+    }
+}
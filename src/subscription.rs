@@ -0,0 +1,137 @@
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+use types::{Record, Recordable};
+
+/// A change to the records of a series, as delivered to its subscribers.
+#[derive(Clone, Debug)]
+pub enum Event<T: Clone + Recordable> {
+    /// A record was added.
+    Put(Record<T>),
+
+    /// A record was replaced with a new version.
+    Update { old: Record<T>, new: Record<T> },
+
+    /// A record was deleted.
+    Delete(Record<T>),
+}
+
+impl<T> Event<T>
+where
+    T: Clone + Recordable,
+{
+    pub(crate) fn new(old: Option<Record<T>>, new: Option<Record<T>>) -> Option<Event<T>> {
+        match (old, new) {
+            (None, Some(new)) => Some(Event::Put(new)),
+            (Some(old), Some(new)) => Some(Event::Update { old, new }),
+            (Some(old), None) => Some(Event::Delete(old)),
+            (None, None) => None,
+        }
+    }
+
+    /// The versions of the record that the event involves: the new one, the old one, or both.
+    fn records(&self) -> Vec<&Record<T>> {
+        match self {
+            Event::Put(record) | Event::Delete(record) => vec![record],
+            Event::Update { old, new } => vec![old, new],
+        }
+    }
+}
+
+/// Identifies a subscription to a series, so that it can be cancelled with
+/// `Series::unsubscribe`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SubscriptionId(usize);
+
+pub(crate) type Filter<T> = Box<dyn Fn(&Record<T>) -> bool + Send + Sync>;
+pub(crate) type Callback<T> = Box<dyn FnMut(&Event<T>) + Send>;
+
+enum Sink<T: Clone + Recordable> {
+    Callback(Mutex<Callback<T>>),
+    Channel(Mutex<Sender<Event<T>>>),
+}
+
+struct Subscriber<T: Clone + Recordable> {
+    id: SubscriptionId,
+    filter: Filter<T>,
+    sink: Sink<T>,
+}
+
+/// Everything subscribed to the changes of a series.
+pub(crate) struct Subscribers<T: Clone + Recordable> {
+    next_id: usize,
+    subscribers: Vec<Subscriber<T>>,
+}
+
+impl<T> Subscribers<T>
+where
+    T: Clone + Recordable,
+{
+    pub(crate) fn new() -> Subscribers<T> {
+        Subscribers {
+            next_id: 0,
+            subscribers: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, filter: Filter<T>, sink: Sink<T>) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push(Subscriber { id, filter, sink });
+        id
+    }
+
+    pub(crate) fn add_callback(
+        &mut self,
+        filter: Filter<T>,
+        callback: Callback<T>,
+    ) -> SubscriptionId {
+        self.add(filter, Sink::Callback(Mutex::new(callback)))
+    }
+
+    pub(crate) fn add_channel(
+        &mut self,
+        filter: Filter<T>,
+        sender: Sender<Event<T>>,
+    ) -> SubscriptionId {
+        self.add(filter, Sink::Channel(Mutex::new(sender)))
+    }
+
+    pub(crate) fn remove(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscribers.len();
+        self.subscribers.retain(|subscriber| subscriber.id != id);
+        self.subscribers.len() < before
+    }
+
+    /// Deliver an event to every subscriber whose filter matches either version of the record.
+    /// Channels whose receiver has been dropped are unsubscribed.
+    pub(crate) fn notify(&mut self, event: &Event<T>) {
+        let records = event.records();
+        let mut disconnected = Vec::new();
+        for subscriber in &mut self.subscribers {
+            if !records.iter().any(|record| (subscriber.filter)(record)) {
+                continue;
+            }
+            // The sinks are only behind a mutex so that the series stays `Sync`; nothing else can
+            // hold the lock.
+            match subscriber.sink {
+                Sink::Callback(ref mut callback) => {
+                    if let Ok(callback) = callback.get_mut() {
+                        callback(event);
+                    }
+                }
+                Sink::Channel(ref mut sender) => {
+                    let sent = match sender.get_mut() {
+                        Ok(sender) => sender.send(event.clone()).is_ok(),
+                        Err(_) => false,
+                    };
+                    if !sent {
+                        disconnected.push(subscriber.id);
+                    }
+                }
+            }
+        }
+        self.subscribers
+            .retain(|subscriber| !disconnected.contains(&subscriber.id));
+    }
+}
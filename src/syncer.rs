@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct State {
+    file: Mutex<File>,
+
+    /// Whether anything has been written since the last sync.
+    dirty: AtomicBool,
+
+    stop: AtomicBool,
+}
+
+/// A thread that syncs a series file on a timer, for `SyncPolicy::Interval`. It syncs the file
+/// once every interval in which something was written to it, and stops when it is dropped.
+pub(crate) struct Syncer {
+    state: Arc<State>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Syncer {
+    /// Start syncing `file`, which should be a handle of its own on the series file, every
+    /// `interval`.
+    pub(crate) fn start(file: File, interval: Duration) -> Syncer {
+        let state = Arc::new(State {
+            file: Mutex::new(file),
+            dirty: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+        });
+        let shared = state.clone();
+        let thread = thread::spawn(move || loop {
+            let deadline = Instant::now() + interval;
+            loop {
+                if shared.stop.load(Ordering::SeqCst) {
+                    return;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                thread::park_timeout(deadline - now);
+            }
+            if shared.dirty.swap(false, Ordering::SeqCst) {
+                let synced = match shared.file.lock() {
+                    Ok(file) => file.sync_data().is_ok(),
+                    Err(_) => false,
+                };
+                if !synced {
+                    // Try again at the end of the next interval.
+                    shared.dirty.store(true, Ordering::SeqCst);
+                }
+            }
+        });
+        Syncer {
+            state,
+            thread: Some(thread),
+        }
+    }
+
+    /// Note that a line was written, and needs syncing.
+    pub(crate) fn wrote(&self) {
+        self.state.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Note that the file was synced by other means.
+    pub(crate) fn synced(&self) {
+        self.state.dirty.store(false, Ordering::SeqCst);
+    }
+
+    /// Whether something was written since the last sync.
+    #[cfg(test)]
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.state.dirty.load(Ordering::SeqCst)
+    }
+
+    /// Sync `file` from now on, in place of the file the series replaced.
+    pub(crate) fn replace_file(&self, file: File) {
+        if let Ok(mut current) = self.state.file.lock() {
+            *current = file;
+        }
+    }
+}
+
+impl Drop for Syncer {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
@@ -3,7 +3,7 @@ extern crate serde;
 extern crate serde_json;
 extern crate uuid;
 
-use self::serde::de::DeserializeOwned;
+use self::serde::de::{DeserializeOwned, IgnoredAny};
 use self::serde::ser::Serialize;
 use self::uuid::Uuid;
 use std::error;
@@ -27,6 +27,29 @@ pub enum Error {
 
     /// Indicates a general IO error
     IOError(io::Error),
+
+    /// Indicates that another process holds a lock on the series file which conflicts with the
+    /// lock this one needs
+    FileLocked,
+
+    /// Indicates that a query string could not be parsed. The position is the byte offset in the
+    /// query at which the problem was found.
+    QueryParseError { position: usize, message: String },
+
+    /// Indicates that a record was not at the expected revision when it was to be updated, because
+    /// it was written or deleted since it was read. `actual` is `None` if the record no longer
+    /// exists.
+    RevisionConflict { expected: u64, actual: Option<u64> },
+
+    /// Indicates that a record to be inserted has the same id as a record already in the series
+    AlreadyExists(UniqueId),
+
+    /// Indicates that a record to be updated is not in the series
+    NotFound(UniqueId),
+
+    /// Indicates that a `Bucket::Fixed` length is not positive, or is so long that a bucket would
+    /// reach beyond the range of times that can be represented
+    InvalidBucket,
 }
 
 
@@ -37,27 +60,51 @@ impl fmt::Display for Error {
             Error::JSONStringError(err) => write!(f, "Error generating a JSON string: {}", err),
             Error::JSONParseError(err) => write!(f, "Error parsing JSON: {}", err),
             Error::IOError(err) => write!(f, "IO Error: {}", err),
+            Error::FileLocked => write!(f, "The series file is locked by another process"),
+            Error::QueryParseError { position, message } => {
+                write!(f, "Error parsing query at position {}: {}", position, message)
+            }
+            Error::RevisionConflict {
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "Expected the record at revision {}, but it is at revision {}",
+                expected, actual
+            ),
+            Error::RevisionConflict {
+                expected,
+                actual: None,
+            } => write!(
+                f,
+                "Expected the record at revision {}, but it has been deleted",
+                expected
+            ),
+            Error::AlreadyExists(id) => write!(f, "A record with id {} already exists", id),
+            Error::NotFound(id) => write!(f, "No record with id {} was found", id),
+            Error::InvalidBucket => write!(
+                f,
+                "A fixed bucket length must be positive, and short enough that every bucket \
+                 lies within the range of times that can be represented"
+            ),
         }
     }
 }
 
 
 impl error::Error for Error {
-    fn description(&self) -> &str {
-        match self {
-            Error::UUIDParseError(ref err) => err.description(),
-            Error::JSONStringError(ref err) => err.description(),
-            Error::JSONParseError(ref err) => err.description(),
-            Error::IOError(ref err) => err.description(),
-        }
-    }
-
     fn cause(&self) -> Option<&dyn error::Error> {
         match self {
             Error::UUIDParseError(ref err) => Some(err),
             Error::JSONStringError(ref err) => Some(err),
             Error::JSONParseError(ref err) => Some(err),
             Error::IOError(ref err) => Some(err),
+            Error::FileLocked => None,
+            Error::QueryParseError { .. } => None,
+            Error::RevisionConflict { .. } => None,
+            Error::AlreadyExists(_) => None,
+            Error::NotFound(_) => None,
+            Error::InvalidBucket => None,
         }
     }
 }
@@ -77,7 +124,7 @@ pub trait Recordable {
 /// Uniquely identifies a record.
 ///
 /// This is a wrapper around a basic uuid with some extra convenience methods.
-#[derive(Clone, Debug, Eq, Hash, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct UniqueId(Uuid);
 
 impl UniqueId {
@@ -88,6 +135,12 @@ impl UniqueId {
     }
 }
 
+impl Default for UniqueId {
+    fn default() -> UniqueId {
+        UniqueId::new()
+    }
+}
+
 impl str::FromStr for UniqueId {
     type Err = Error;
 
@@ -102,7 +155,7 @@ impl str::FromStr for UniqueId {
 impl fmt::Display for UniqueId {
     /// Convert to a hyphenated string
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "{}", self.0.hyphenated().to_string())
+        write!(f, "{}", self.0.hyphenated())
     }
 }
 
@@ -112,6 +165,15 @@ impl fmt::Display for UniqueId {
 pub struct Record<T: Clone + Recordable> {
     pub id: UniqueId,
     pub data: T,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) created_at: Option<DateTimeTz>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) updated_at: Option<DateTimeTz>,
+
+    #[serde(default)]
+    pub(crate) revision: u64,
 }
 
 impl<T> Record<T>
@@ -120,7 +182,35 @@ where
 {
     pub fn new(data: T) -> Record<T> {
         let id = UniqueId::new();
-        Record { id, data }
+        Record {
+            id,
+            data,
+            created_at: None,
+            updated_at: None,
+            revision: 0,
+        }
+    }
+
+    /// When the record was first written to the series, to the second. This is `None` for a
+    /// record that has not been written yet, or that was written by an older version of this
+    /// library.
+    pub fn created_at(&self) -> Option<&DateTimeTz> {
+        self.created_at.as_ref()
+    }
+
+    /// When this version of the record was written to the series, to the second. This is `None`
+    /// for a record that has not been written yet, or that was written by an older version of
+    /// this library.
+    pub fn updated_at(&self) -> Option<&DateTimeTz> {
+        self.updated_at.as_ref()
+    }
+
+    /// The revision at which this version of the record was written. Every line written to a
+    /// series gets a revision higher than that of any other line in its file. This is 0 for a
+    /// record that has not been written yet, or that was written by an older version of this
+    /// library.
+    pub fn revision(&self) -> u64 {
+        self.revision
     }
 }
 
@@ -141,6 +231,15 @@ where
 pub struct DeletableRecord<T: Clone + Recordable> {
     pub id: UniqueId,
     pub data: Option<T>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTimeTz>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTimeTz>,
+
+    #[serde(default)]
+    pub revision: u64,
 }
 
 impl<T> str::FromStr for DeletableRecord<T>
@@ -150,7 +249,7 @@ where
     type Err = Error;
 
     fn from_str(line: &str) -> Result<Self, Self::Err> {
-        serde_json::from_str(&line).map_err(|err| {
+        serde_json::from_str(line).map_err(|err| {
             println!("deserialization error: {}", err);
             Error::JSONParseError(err)
         })
@@ -158,13 +257,103 @@ where
 }
 
 
+/// A single line of the series file. Most lines hold a single record, but a batch of records
+/// committed together shares a line, so that the batch is written or torn as a whole.
+///
+/// A compacted file may also start with a line that holds no records, only the latest revision
+/// written before the compaction, when the record that carried it is gone.
+///
+/// Parse a line with `str::parse`, which tells the shapes apart by their keys before
+/// deserializing, so that a damaged line reports what is actually wrong with it.
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub enum LogLine<T: Clone + Recordable> {
+    Batch { batch: Vec<DeletableRecord<T>> },
+    Single(DeletableRecord<T>),
+    Revision { last_revision: u64 },
+}
+
+impl<T> LogLine<T>
+where
+    T: Clone + Recordable,
+{
+    /// The highest revision in the line.
+    pub fn revision(&self) -> u64 {
+        match self {
+            LogLine::Batch { batch } => batch
+                .iter()
+                .map(|record| record.revision)
+                .max()
+                .unwrap_or(0),
+            LogLine::Single(record) => record.revision,
+            LogLine::Revision { last_revision } => *last_revision,
+        }
+    }
+
+    /// The records in the line, in the order they were written.
+    pub fn into_records(self) -> Vec<DeletableRecord<T>> {
+        match self {
+            LogLine::Batch { batch } => batch,
+            LogLine::Single(record) => vec![record],
+            LogLine::Revision { .. } => Vec::new(),
+        }
+    }
+}
+
+impl<T> str::FromStr for LogLine<T>
+where
+    T: Clone + Recordable + DeserializeOwned + Serialize,
+{
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let shape: LineShape = serde_json::from_str(line).map_err(Error::JSONParseError)?;
+        if shape.batch.is_some() {
+            serde_json::from_str(line)
+                .map(|line: BatchLine<T>| LogLine::Batch { batch: line.batch })
+                .map_err(Error::JSONParseError)
+        } else if shape.last_revision.is_some() {
+            serde_json::from_str(line)
+                .map(|line: RevisionLine| LogLine::Revision {
+                    last_revision: line.last_revision,
+                })
+                .map_err(Error::JSONParseError)
+        } else {
+            serde_json::from_str(line)
+                .map(LogLine::Single)
+                .map_err(Error::JSONParseError)
+        }
+    }
+}
+
+
+/// The keys which tell the shapes of `LogLine` apart.
+#[derive(Deserialize)]
+struct LineShape {
+    batch: Option<IgnoredAny>,
+    last_revision: Option<IgnoredAny>,
+}
+
+
+#[derive(Deserialize)]
+struct BatchLine<T: Clone + Recordable> {
+    batch: Vec<DeletableRecord<T>>,
+}
+
+
+#[derive(Deserialize)]
+struct RevisionLine {
+    last_revision: u64,
+}
+
+
 #[cfg(test)]
 mod test {
     extern crate dimensioned;
     extern crate serde_json;
 
     use self::dimensioned::si::{Kilogram, KG};
-    use super::{DeletableRecord, Recordable};
+    use super::{DeletableRecord, Error, LogLine, Recordable};
     use date_time_tz::DateTimeTz;
     use chrono::TimeZone;
     use chrono_tz::Etc::UTC;
@@ -202,16 +391,19 @@ mod test {
         assert_eq!(
             rec.data,
             Some(WeightRecord {
-                date: DateTimeTz(UTC.ymd(2003, 11, 10).and_hms(6, 0, 0)),
+                date: DateTimeTz(UTC.with_ymd_and_hms(2003, 11, 10, 6, 0, 0).unwrap()),
                 weight: Weight(77.79109 * KG),
             })
         );
+        assert_eq!(rec.created_at, None);
+        assert_eq!(rec.updated_at, None);
+        assert_eq!(rec.revision, 0);
     }
 
     #[test]
     pub fn serialization_output() {
         let rec = WeightRecord {
-            date: DateTimeTz(UTC.ymd(2003, 11, 10).and_hms(6, 0, 0)),
+            date: DateTimeTz(UTC.with_ymd_and_hms(2003, 11, 10, 6, 0, 0).unwrap()),
             weight: Weight(77.0 * KG),
         };
         assert_eq!(
@@ -220,7 +412,7 @@ mod test {
         );
 
         let rec2 = WeightRecord {
-            date: DateTimeTz(Central.ymd(2003, 11, 10).and_hms(0, 0, 0)),
+            date: DateTimeTz(Central.with_ymd_and_hms(2003, 11, 10, 0, 0, 0).unwrap()),
             weight: Weight(77.0 * KG),
         };
         assert_eq!(
@@ -228,4 +420,35 @@ mod test {
             "{\"date\":\"2003-11-10T06:00:00Z US/Central\",\"weight\":77.0}"
         );
     }
+
+    #[test]
+    pub fn log_lines_report_what_is_wrong_with_them() {
+        let line = WEIGHT_ENTRY.replace("77.79109", "\"heavy\"");
+        match line.parse::<LogLine<WeightRecord>>() {
+            Err(Error::JSONParseError(err)) => {
+                assert!(!err.to_string().contains("untagged"), "{}", err);
+                assert_eq!(err.line(), 1);
+                assert!(err.column() > 0);
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("the line should not parse"),
+        }
+
+        let batch = format!("{{\"batch\":[{}]}}", line);
+        match batch.parse::<LogLine<WeightRecord>>() {
+            Err(Error::JSONParseError(err)) => {
+                assert!(!err.to_string().contains("untagged"), "{}", err)
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("the batch should not parse"),
+        }
+
+        let batch = format!("{{\"batch\":[{}]}}", WEIGHT_ENTRY);
+        let parsed: LogLine<WeightRecord> = batch.parse().expect("the batch should parse");
+        assert_eq!(parsed.into_records().len(), 1);
+        let parsed: LogLine<WeightRecord> = "{\"last_revision\":7}"
+            .parse()
+            .expect("the revision should parse");
+        assert_eq!(parsed.revision(), 7);
+    }
 }